@@ -0,0 +1,145 @@
+use crate::config::{ControlMode, ControllerKind};
+use crate::env::RewardKind;
+use crate::export::ExportFormat;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+// Command-line interface. Without a subcommand the simulator behaves like `play`.
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Four-way road intersection traffic simulation",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub play: PlayArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Open the simulation window and drive it from the keyboard (default)
+    Play(PlayArgs),
+    /// Run for a fixed number of ticks with random demand, for batch jobs
+    Run(RunArgs),
+    /// Play back a recorded run
+    Replay(ReplayArgs),
+    /// Let an external agent pick the phases over JSON lines on stdin/stdout
+    Env(EnvArgs),
+}
+
+// Options that decide how the simulation itself is set up
+#[derive(Args, Clone)]
+pub struct SimArgs {
+    /// TOML config file [default: config.toml if present]
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Seed for all randomness [default: random]
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Signals, an all-way stop or yield signs, overriding the config
+    #[arg(long, value_enum)]
+    pub control: Option<ControlMode>,
+
+    /// Signal controller, overriding the config
+    #[arg(long, value_enum)]
+    pub controller: Option<ControllerKind>,
+
+    /// Start from a saved snapshot instead of an empty intersection
+    #[arg(long, value_name = "FILE")]
+    pub start_from: Option<PathBuf>,
+
+    /// Scenario file with scripted spawns, closures and forced lights
+    #[arg(long, value_name = "FILE")]
+    pub scenario: Option<PathBuf>,
+}
+
+// Options that decide what a run writes out
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Export trajectories, light states, events and a summary
+    #[arg(long, value_enum)]
+    pub export: Option<ExportFormat>,
+
+    /// Directory for exported files
+    #[arg(long, value_name = "DIR", default_value = "output")]
+    pub output_dir: PathBuf,
+
+    /// Record the run to a replay file
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Print vehicle lifecycle events to stdout
+    #[arg(long)]
+    pub log_events: bool,
+}
+
+#[derive(Args)]
+pub struct PlayArgs {
+    #[command(flatten)]
+    pub sim: SimArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Snapshot file used by F5 (save) and F9 (restore)
+    #[arg(long, value_name = "FILE", default_value = "snapshot.json")]
+    pub snapshot: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub sim: SimArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Number of ticks to simulate (60 ticks per simulated second) [default: the scenario's ticks]
+    #[arg(long)]
+    pub ticks: Option<u64>,
+
+    /// Run as fast as possible without opening a window
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Don't generate random vehicles (useful with --start-from or --scenario)
+    #[arg(long)]
+    pub no_random_spawns: bool,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Replay file written with --record
+    pub file: PathBuf,
+
+    /// Check the recording plays back identically without opening a window
+    #[arg(long)]
+    pub headless: bool,
+}
+
+#[derive(Args)]
+pub struct EnvArgs {
+    #[command(flatten)]
+    pub sim: SimArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Ticks simulated per step, i.e. how often the agent picks a phase
+    #[arg(long, default_value_t = 60)]
+    pub ticks_per_step: u64,
+
+    /// Ticks per episode before a step reports done (216000 is one simulated hour)
+    #[arg(long, default_value_t = 216_000)]
+    pub episode_ticks: u64,
+
+    /// Quantity the reward is based on
+    #[arg(long, value_enum, default_value_t = RewardKind::Delay)]
+    pub reward: RewardKind,
+}
@@ -0,0 +1,1252 @@
+use crate::{Direction, Route, TICKS_PER_SECOND};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Runtime settings, loaded from a TOML file. Every field has a default so a
+// config file only needs to list what it changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub road: RoadConfig,
+    pub intersection: IntersectionConfig,
+    pub vehicles: VehicleConfig,
+    pub signals: SignalConfig,
+    pub demand: DemandConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoadConfig {
+    pub width: u32,
+    pub turn_offset: i32, // How far past the centre line vehicles travel before turning
+    pub drive_on: DriveOn,
+    pub speed_limit: Vec<SpeedLimit>, // Arms with their own limits; the rest use vehicles.speed
+    pub turn_speed: Option<f64>,      // Limit for turning through the box; none by default
+}
+
+// Speed limits on one arm, in pixels per tick. Either may be left out to
+// use vehicles.speed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpeedLimit {
+    pub arm: Arm,
+    pub approach: Option<f64>, // Coming in on this arm
+    pub exit: Option<f64>,     // Going out on it
+}
+
+// Side of the road traffic keeps to. Turns are laid out for driving on the
+// left; driving on the right is their mirror image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriveOn {
+    #[default]
+    Left,
+    Right,
+}
+
+// How right of way is decided at the intersection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntersectionConfig {
+    pub no_u_turn: Vec<Arm>, // Arms vehicles coming in on may not turn back from
+    pub control: ControlMode,
+    pub stop_ms: u64,          // Full stop each vehicle makes at an all-way stop
+    pub major_road: MajorRoad, // Road with priority when the minor approaches yield
+    pub gap_ms: u64,           // Shortest gap in major-road traffic a yielding vehicle accepts
+    pub reservation: ReservationConfig,
+    pub roundabout: RoundaboutConfig,
+}
+
+// Signal-free control where vehicles reserve space and time in the box
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReservationConfig {
+    pub grid: i32,             // The box is divided into grid x grid cells
+    pub margin_ms: u64,        // Time left between two vehicles using the same cell
+    pub request_distance: i32, // Pixels before the stop line a vehicle asks for its reservation
+}
+
+// Single-lane roundabout in place of the crossing; roundabout.rs has which
+// exit each route takes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoundaboutConfig {
+    pub radius: i32, // From the centre to the middle of the circulating lane, in pixels
+    pub gap_ms: u64, // Shortest gap in circulating traffic an entering vehicle accepts
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlMode {
+    // Traffic lights run by the configured signal controller
+    #[default]
+    Signals,
+    // Stop signs on every approach, first come first served
+    #[value(alias = "all_way_stop")]
+    AllWayStop,
+    // Priority road, with yield signs on the minor approaches
+    Yield,
+    // No signs or lights; vehicles reserve their path through the box
+    Reservation,
+    // Roundabout, entering vehicles yield to those already on it
+    Roundabout,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MajorRoad {
+    #[default]
+    NorthSouth,
+    EastWest,
+}
+
+impl IntersectionConfig {
+    // Whether the movement is allowed from this approach
+    pub fn serves(&self, direction: Direction, route: Route) -> bool {
+        route != Route::UTurn || !self.no_u_turn.iter().any(|arm| arm.entering() == direction)
+    }
+}
+
+impl MajorRoad {
+    // Whether vehicles travelling in this direction are on the major road
+    pub fn carries(self, direction: Direction) -> bool {
+        match self {
+            MajorRoad::NorthSouth => matches!(direction, Direction::North | Direction::South),
+            MajorRoad::EastWest => matches!(direction, Direction::East | Direction::West),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VehicleConfig {
+    pub speed: i32,        // Pixels per tick; the limit on roads without their own
+    pub speed_spread: f64, // Spread of drivers' desired speeds around the limit, as a fraction of it
+    pub min_distance: i32,
+    pub spawn_cooldown_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignalConfig {
+    pub controller: ControllerKind,
+    pub min_green_ms: u64, // Shortest green before the priority controller may switch away
+    pub max_green_ms: u64,
+    pub priority_threshold: u32, // Queue length that lets an approach take the green early
+    pub switch_margin: u32,      // Vehicles a lane must lead the green lane by to cut in
+    pub max_wait_ms: u64, // A vehicle standing this long forces its approach green; 0 turns it off
+    pub detectors: DetectorConfig,
+    pub actuated: ActuatedConfig,
+    pub max_pressure: MaxPressureConfig,
+    pub webster: WebsterConfig,
+    pub flash: FlashConfig,
+}
+
+// Virtual loop detectors on every approach, measured in pixels upstream of
+// where vehicles stop for the light
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectorConfig {
+    pub presence_length: i32, // Presence zone from the stop line back
+    pub passage_distance: i32,
+    pub passage_length: i32,
+}
+
+// Fully actuated control: each approach gets at least min green, is extended
+// while the detectors keep seeing vehicles, and ends on a gap or at max green
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActuatedConfig {
+    pub min_green_ms: u64,
+    pub max_green_ms: u64,
+    pub passage_ms: u64, // Green ends once no vehicle has been detected for this long
+}
+
+// Signal control strategies selectable from the config or the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ControllerKind {
+    // Serves the longest queue, letting busy approaches cut in
+    #[default]
+    Priority,
+    // Detector-driven min green, gap-out and max-out
+    Actuated,
+    // Largest queue-minus-downstream pressure, re-evaluated at a fixed interval
+    #[value(alias = "max_pressure")]
+    MaxPressure,
+    // Fixed-time plan with Webster's optimal cycle and green splits
+    Webster,
+    // Phase chosen each step by an agent over the env protocol; all red until told
+    #[value(skip)]
+    External,
+}
+
+// Automatic vehicle arrivals, keyed by direction of travel. Approaches with
+// no volume generate nothing, so demand is off unless configured.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DemandConfig {
+    pub northbound: ApproachDemand,
+    pub southbound: ApproachDemand,
+    pub eastbound: ApproachDemand,
+    pub westbound: ApproachDemand,
+    pub start_time: ClockTime, // Time of day at tick 0
+    // Time-of-day volumes, read into `profile` when the config is loaded
+    pub profile_file: Option<PathBuf>,
+    // When not empty, replaces each approach's vehicles_per_hour
+    pub profile: Vec<DemandInterval>,
+    // Origin-destination volumes, read into `od` when the config is loaded
+    pub od_file: Option<PathBuf>,
+    // When not empty, sets both the volumes and the turning movements
+    pub od: Vec<OdRow>,
+}
+
+// Vehicles per hour entering from one arm of the intersection, by the arm
+// they leave through
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OdRow {
+    pub from: Arm,
+    pub north: f64,
+    pub south: f64,
+    pub east: f64,
+    pub west: f64,
+}
+
+// A road leaving the intersection. Vehicles entering from the north arm
+// travel south; vehicles leaving by the north arm travel north.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Arm {
+    North,
+    South,
+    East,
+    West,
+}
+
+// Volumes from `time` until the next interval starts; the last one runs past
+// midnight into the first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DemandInterval {
+    pub time: ClockTime,
+    pub northbound: f64,
+    pub southbound: f64,
+    pub eastbound: f64,
+    pub westbound: f64,
+}
+
+// Minutes since midnight, written as "HH:MM"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ClockTime(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApproachDemand {
+    pub process: ArrivalProcess,
+    pub vehicles_per_hour: f64,
+    // Relative turning proportions, normalised when a route is drawn
+    pub straight: f64,
+    pub left: f64,
+    pub right: f64,
+    pub u_turn: f64,
+    pub platoon_size: u32,       // Vehicles per platoon
+    pub platoon_headway_ms: u64, // Gap between vehicles inside a platoon
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrivalProcess {
+    // Exponentially distributed gaps between vehicles
+    #[default]
+    Poisson,
+    // A vehicle exactly every 3600 / vehicles_per_hour seconds
+    Uniform,
+    // Poisson-arriving platoons of closely spaced vehicles, with the same mean volume
+    Platoon,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 900,
+            height: 800,
+        }
+    }
+}
+
+impl Default for RoadConfig {
+    fn default() -> Self {
+        RoadConfig {
+            width: 70,
+            turn_offset: 30,
+            drive_on: DriveOn::Left,
+            speed_limit: Vec::new(),
+            turn_speed: None,
+        }
+    }
+}
+
+impl Default for IntersectionConfig {
+    fn default() -> Self {
+        IntersectionConfig {
+            no_u_turn: Vec::new(),
+            control: ControlMode::Signals,
+            stop_ms: 1000,
+            major_road: MajorRoad::NorthSouth,
+            gap_ms: 2000,
+            reservation: ReservationConfig::default(),
+            roundabout: RoundaboutConfig::default(),
+        }
+    }
+}
+
+impl Default for ReservationConfig {
+    fn default() -> Self {
+        ReservationConfig {
+            grid: 4,
+            margin_ms: 300,
+            request_distance: 150,
+        }
+    }
+}
+
+impl Default for RoundaboutConfig {
+    fn default() -> Self {
+        RoundaboutConfig {
+            radius: 70,
+            gap_ms: 1000,
+        }
+    }
+}
+
+impl Default for VehicleConfig {
+    fn default() -> Self {
+        VehicleConfig {
+            speed: 2,
+            speed_spread: 0.0,
+            min_distance: 50,
+            spawn_cooldown_ms: 1000,
+        }
+    }
+}
+
+impl Default for SignalConfig {
+    fn default() -> Self {
+        SignalConfig {
+            controller: ControllerKind::Priority,
+            min_green_ms: 2000,
+            max_green_ms: 4000,
+            priority_threshold: 4,
+            switch_margin: 2,
+            max_wait_ms: 20000,
+            detectors: DetectorConfig::default(),
+            actuated: ActuatedConfig::default(),
+            max_pressure: MaxPressureConfig::default(),
+            webster: WebsterConfig::default(),
+            flash: FlashConfig::default(),
+        }
+    }
+}
+
+impl Default for ApproachDemand {
+    fn default() -> Self {
+        ApproachDemand {
+            process: ArrivalProcess::Poisson,
+            vehicles_per_hour: 0.0,
+            straight: 1.0,
+            left: 1.0,
+            right: 1.0,
+            u_turn: 0.0,
+            platoon_size: 5,
+            platoon_headway_ms: 1500,
+        }
+    }
+}
+
+impl DemandConfig {
+    pub fn approach(&self, direction: Direction) -> &ApproachDemand {
+        match direction {
+            Direction::North => &self.northbound,
+            Direction::South => &self.southbound,
+            Direction::East => &self.eastbound,
+            Direction::West => &self.westbound,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        let directions = [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ];
+        directions
+            .iter()
+            .any(|&direction| self.approach(direction).vehicles_per_hour > 0.0)
+            || self.profile.iter().any(|interval| {
+                directions
+                    .iter()
+                    .any(|&direction| interval.volume(direction) > 0.0)
+            })
+            || !self.od.is_empty()
+    }
+
+    fn od_row(&self, direction: Direction) -> Option<&OdRow> {
+        self.od.iter().find(|row| row.from.entering() == direction)
+    }
+
+    pub fn time_of_day(&self, tick: u64) -> ClockTime {
+        let seconds = self.start_time.0 as u64 * 60 + tick / TICKS_PER_SECOND as u64;
+        ClockTime(((seconds / 60) % (24 * 60)) as u32)
+    }
+
+    // Vehicles per hour on an approach at the given tick, from the profile if there is one
+    pub fn vehicles_per_hour(&self, direction: Direction, tick: u64) -> f64 {
+        if !self.od.is_empty() {
+            return self.od_row(direction).map_or(0.0, |row| {
+                ROUTES.iter().map(|&route| row.volume(route)).sum()
+            });
+        }
+        if self.profile.is_empty() {
+            return self.approach(direction).vehicles_per_hour;
+        }
+        let minute = self.time_of_day(tick).0;
+        self.profile
+            .iter()
+            .rev()
+            .find(|interval| interval.time.0 <= minute)
+            .or(self.profile.last())
+            .map_or(0.0, |interval| interval.volume(direction))
+    }
+
+    // Draws a route from the OD matrix if there is one, otherwise from the
+    // approach's turning proportions for the movements allowed there
+    pub fn route(&self, direction: Direction, draw: f64, served: impl Fn(Route) -> bool) -> Route {
+        let Some(row) = self.od_row(direction) else {
+            return self.approach(direction).route(draw, served);
+        };
+        let total: f64 = ROUTES.iter().map(|&route| row.volume(route)).sum();
+        let mut x = draw * total;
+        for route in ROUTES {
+            x -= row.volume(route);
+            if x < 0.0 {
+                return route;
+            }
+        }
+        Route::Right
+    }
+
+    // Reads the CSV profile and OD tables, with paths relative to the config file
+    fn load_tables(&mut self, config_path: &Path) -> Result<(), String> {
+        let dir = config_path.parent().unwrap_or(Path::new(""));
+        if let Some(file) = &self.profile_file {
+            if !self.profile.is_empty() {
+                return Err(
+                    "set either demand.profile_file or demand.profile, not both".to_string()
+                );
+            }
+            self.profile = read_table(&dir.join(file))?;
+        }
+        if let Some(file) = &self.od_file {
+            if !self.od.is_empty() {
+                return Err("set either demand.od_file or demand.od, not both".to_string());
+            }
+            self.od = read_table(&dir.join(file))?;
+        }
+        Ok(())
+    }
+}
+
+const ROUTES: [Route; 4] = [Route::Straight, Route::Left, Route::Right, Route::UTurn];
+
+fn read_table<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Vec<T>, String> {
+    let mut reader = csv::Reader::from_path(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    reader
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Invalid table {}: {}", path.display(), e))
+}
+
+impl ApproachDemand {
+    // Mean number of ticks between vehicles at the given volume
+    pub fn mean_gap_ticks(vehicles_per_hour: f64) -> f64 {
+        3600.0 * TICKS_PER_SECOND as f64 / vehicles_per_hour
+    }
+
+    // Picks a route according to the turning proportions, among those served
+    pub fn route(&self, draw: f64, served: impl Fn(Route) -> bool) -> Route {
+        let straight = self.share(Route::Straight, &served);
+        let left = self.share(Route::Left, &served);
+        let right = self.share(Route::Right, &served);
+        let u_turn = self.share(Route::UTurn, &served);
+        let x = draw * (straight + left + right + u_turn);
+        if x < straight {
+            Route::Straight
+        } else if x < straight + left {
+            Route::Left
+        } else if x < straight + left + right {
+            Route::Right
+        } else {
+            Route::UTurn
+        }
+    }
+
+    // Turning proportion of a route, or 0 where it isn't served
+    fn share(&self, route: Route, served: impl Fn(Route) -> bool) -> f64 {
+        if !served(route) {
+            return 0.0;
+        }
+        match route {
+            Route::Straight => self.straight,
+            Route::Left => self.left,
+            Route::Right => self.right,
+            Route::UTurn => self.u_turn,
+        }
+    }
+
+    fn validate(&self, name: &str, errors: &mut Vec<String>) {
+        if !(self.vehicles_per_hour >= 0.0 && self.vehicles_per_hour.is_finite()) {
+            errors.push(format!(
+                "demand.{}.vehicles_per_hour = {} must be a non-negative number",
+                name, self.vehicles_per_hour
+            ));
+        }
+        let proportions = [self.straight, self.left, self.right, self.u_turn];
+        if proportions.iter().any(|p| !(*p >= 0.0 && p.is_finite()))
+            || proportions.iter().sum::<f64>() <= 0.0
+        {
+            errors.push(format!(
+                "demand.{}: straight, left, right and u_turn must be non-negative and not all 0",
+                name
+            ));
+        }
+        if self.platoon_size == 0 {
+            errors.push(format!("demand.{}.platoon_size must be at least 1", name));
+        }
+    }
+}
+
+impl TryFrom<String> for ClockTime {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let invalid = || format!("invalid time \"{}\", expected HH:MM", text);
+        let (hours, minutes) = text.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.trim().parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.trim().parse().map_err(|_| invalid())?;
+        if hours >= 24 || minutes >= 60 {
+            return Err(invalid());
+        }
+        Ok(ClockTime(hours * 60 + minutes))
+    }
+}
+
+impl From<ClockTime> for String {
+    fn from(time: ClockTime) -> String {
+        format!("{:02}:{:02}", time.0 / 60, time.0 % 60)
+    }
+}
+
+impl DemandInterval {
+    fn volume(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::North => self.northbound,
+            Direction::South => self.southbound,
+            Direction::East => self.eastbound,
+            Direction::West => self.westbound,
+        }
+    }
+}
+
+impl Arm {
+    fn name(self) -> &'static str {
+        match self {
+            Arm::North => "north",
+            Arm::South => "south",
+            Arm::East => "east",
+            Arm::West => "west",
+        }
+    }
+
+    // Direction of travel of vehicles coming in from this arm
+    pub fn entering(self) -> Direction {
+        match self {
+            Arm::North => Direction::South,
+            Arm::South => Direction::North,
+            Arm::East => Direction::West,
+            Arm::West => Direction::East,
+        }
+    }
+
+    // Direction of travel of vehicles going out through this arm
+    pub fn leaving(self) -> Direction {
+        match self {
+            Arm::North => Direction::North,
+            Arm::South => Direction::South,
+            Arm::East => Direction::East,
+            Arm::West => Direction::West,
+        }
+    }
+}
+
+impl OdRow {
+    // Volume leaving in the given direction of travel
+    fn to(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::North => self.north,
+            Direction::South => self.south,
+            Direction::East => self.east,
+            Direction::West => self.west,
+        }
+    }
+
+    fn volume(&self, route: Route) -> f64 {
+        self.to(self.from.entering().after(route))
+    }
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        DetectorConfig {
+            presence_length: 40,
+            passage_distance: 120,
+            passage_length: 10,
+        }
+    }
+}
+
+impl Default for ActuatedConfig {
+    fn default() -> Self {
+        ActuatedConfig {
+            min_green_ms: 3000,
+            max_green_ms: 15000,
+            passage_ms: 1500,
+        }
+    }
+}
+
+// Max-pressure control re-evaluates the phase at a fixed interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaxPressureConfig {
+    pub interval_ms: u64,
+}
+
+impl Default for MaxPressureConfig {
+    fn default() -> Self {
+        MaxPressureConfig { interval_ms: 2000 }
+    }
+}
+
+// Flashing operation, e.g. overnight. Between `from` and `until` (time of day,
+// counted from demand.start_time) the lights flash instead of cycling.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlashConfig {
+    pub mode: FlashMode,
+    pub from: Option<ClockTime>,
+    pub until: Option<ClockTime>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashMode {
+    Off,
+    // Flashing yellow on the major road, flashing red on the minor approaches
+    #[default]
+    Yellow,
+    // Flashing red everywhere, run as an all-way stop
+    Red,
+}
+
+impl FlashConfig {
+    // Scheduled mode at a time of day; the window may run past midnight
+    pub fn scheduled(&self, time: ClockTime) -> FlashMode {
+        let (Some(from), Some(until)) = (self.from, self.until) else {
+            return FlashMode::Off;
+        };
+        let active = if from <= until {
+            from <= time && time < until
+        } else {
+            time >= from || time < until
+        };
+        if active { self.mode } else { FlashMode::Off }
+    }
+}
+
+// Fixed-time plan from Webster's formula. Flows are measured volumes per
+// approach; with reoptimise_ms set, later plans use the passage detector counts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebsterConfig {
+    pub lost_time_ms: u64, // Per phase, shown as all-red after each green
+    pub min_green_ms: u64,
+    pub min_cycle_ms: u64,
+    pub max_cycle_ms: u64,
+    pub reoptimise_ms: u64, // 0 keeps the first plan for the whole run
+    pub northbound: WebsterApproach,
+    pub southbound: WebsterApproach,
+    pub eastbound: WebsterApproach,
+    pub westbound: WebsterApproach,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebsterApproach {
+    pub flow: f64,            // Vehicles per hour
+    pub saturation_flow: f64, // Vehicles per hour of green
+}
+
+impl Default for WebsterConfig {
+    fn default() -> Self {
+        WebsterConfig {
+            lost_time_ms: 2000,
+            min_green_ms: 4000,
+            min_cycle_ms: 30000,
+            max_cycle_ms: 120000,
+            reoptimise_ms: 0,
+            northbound: WebsterApproach::default(),
+            southbound: WebsterApproach::default(),
+            eastbound: WebsterApproach::default(),
+            westbound: WebsterApproach::default(),
+        }
+    }
+}
+
+impl Default for WebsterApproach {
+    fn default() -> Self {
+        WebsterApproach {
+            flow: 0.0,
+            saturation_flow: 1800.0,
+        }
+    }
+}
+
+impl WebsterConfig {
+    pub fn approach(&self, direction: Direction) -> &WebsterApproach {
+        match direction {
+            Direction::North => &self.northbound,
+            Direction::South => &self.southbound,
+            Direction::East => &self.eastbound,
+            Direction::West => &self.westbound,
+        }
+    }
+}
+
+// Converts milliseconds of simulated time into ticks
+pub fn ticks_from_ms(ms: u64) -> u64 {
+    ms * TICKS_PER_SECOND as u64 / 1000
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        config
+            .demand
+            .load_tables(path)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    // Reports every problem at once rather than stopping at the first one
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        let min_road_width = 2 * crate::VEHICLE_WIDTH;
+
+        if self.window.width < 200 || self.window.height < 200 {
+            errors.push(format!(
+                "window size {}x{} is too small (minimum 200x200)",
+                self.window.width, self.window.height
+            ));
+        }
+        if self.road.width < min_road_width {
+            errors.push(format!(
+                "road.width = {} must be at least {} so both lanes fit a vehicle",
+                self.road.width, min_road_width
+            ));
+        }
+        if self.road.width >= self.window.width.min(self.window.height) / 2 {
+            errors.push(format!(
+                "road.width = {} must be less than half the smaller window dimension",
+                self.road.width
+            ));
+        }
+        if self.road.turn_offset < 0 || self.road.turn_offset > self.road.width as i32 / 2 {
+            errors.push(format!(
+                "road.turn_offset = {} must be between 0 and half the road width ({})",
+                self.road.turn_offset,
+                self.road.width / 2
+            ));
+        }
+        // Stop lines are detected in a 5 pixel window, faster vehicles would skip it
+        if !(1..=5).contains(&self.vehicles.speed) {
+            errors.push(format!(
+                "vehicles.speed = {} must be between 1 and 5 pixels per tick",
+                self.vehicles.speed
+            ));
+        }
+        // Same bound for the other limits, which may be fractional
+        let valid_speed = |speed: f64| speed > 0.0 && speed <= 5.0;
+        for (i, limit) in self.road.speed_limit.iter().enumerate() {
+            let name = limit.arm.name();
+            if self.road.speed_limit[..i]
+                .iter()
+                .any(|l| l.arm == limit.arm)
+            {
+                errors.push(format!("road.speed_limit lists {} twice", name));
+            }
+            for (link, speed) in [("approach", limit.approach), ("exit", limit.exit)] {
+                if let Some(speed) = speed
+                    && !valid_speed(speed)
+                {
+                    errors.push(format!(
+                        "road.speed_limit for {}: {} = {} must be more than 0 and at most 5 pixels per tick",
+                        name, link, speed
+                    ));
+                }
+            }
+        }
+        if let Some(speed) = self.road.turn_speed
+            && !valid_speed(speed)
+        {
+            errors.push(format!(
+                "road.turn_speed = {} must be more than 0 and at most 5 pixels per tick",
+                speed
+            ));
+        }
+        if !(0.0..=0.5).contains(&self.vehicles.speed_spread) {
+            errors.push(format!(
+                "vehicles.speed_spread = {} must be between 0 and 0.5",
+                self.vehicles.speed_spread
+            ));
+        }
+        if self.vehicles.min_distance < 0 {
+            errors.push(format!(
+                "vehicles.min_distance = {} must not be negative",
+                self.vehicles.min_distance
+            ));
+        }
+        if self.signals.max_green_ms == 0 {
+            errors.push("signals.max_green_ms must be greater than 0".to_string());
+        }
+        if self.signals.min_green_ms > self.signals.max_green_ms {
+            errors.push(format!(
+                "signals.min_green_ms = {} must be at most max_green_ms ({})",
+                self.signals.min_green_ms, self.signals.max_green_ms
+            ));
+        }
+        if self.signals.priority_threshold == 0 {
+            errors.push("signals.priority_threshold must be at least 1".to_string());
+        }
+        let detectors = &self.signals.detectors;
+        if detectors.presence_length < 1 || detectors.passage_length < 1 {
+            errors.push("signals.detectors lengths must be at least 1 pixel".to_string());
+        }
+        if detectors.passage_length >= self.vehicles.min_distance {
+            errors.push(format!(
+                "signals.detectors.passage_length = {} must be shorter than vehicles.min_distance ({}) so each vehicle is counted",
+                detectors.passage_length, self.vehicles.min_distance
+            ));
+        }
+        if detectors.passage_distance < 0 {
+            errors.push(format!(
+                "signals.detectors.passage_distance = {} must not be negative",
+                detectors.passage_distance
+            ));
+        }
+        let actuated = &self.signals.actuated;
+        if actuated.min_green_ms == 0 || actuated.min_green_ms > actuated.max_green_ms {
+            errors.push(format!(
+                "signals.actuated.min_green_ms = {} must be greater than 0 and at most max_green_ms ({})",
+                actuated.min_green_ms, actuated.max_green_ms
+            ));
+        }
+        if actuated.passage_ms == 0 {
+            errors.push("signals.actuated.passage_ms must be greater than 0".to_string());
+        }
+        if self.signals.max_pressure.interval_ms == 0 {
+            errors.push("signals.max_pressure.interval_ms must be greater than 0".to_string());
+        }
+        let webster = &self.signals.webster;
+        if webster.min_green_ms == 0 {
+            errors.push("signals.webster.min_green_ms must be greater than 0".to_string());
+        }
+        if webster.min_cycle_ms > webster.max_cycle_ms {
+            errors.push(format!(
+                "signals.webster.min_cycle_ms = {} must not exceed max_cycle_ms ({})",
+                webster.min_cycle_ms, webster.max_cycle_ms
+            ));
+        }
+        for (name, approach) in [
+            ("northbound", &webster.northbound),
+            ("southbound", &webster.southbound),
+            ("eastbound", &webster.eastbound),
+            ("westbound", &webster.westbound),
+        ] {
+            if !(approach.flow >= 0.0 && approach.flow.is_finite()) {
+                errors.push(format!(
+                    "signals.webster.{}.flow = {} must be a non-negative number",
+                    name, approach.flow
+                ));
+            }
+            if !(approach.saturation_flow > 0.0 && approach.saturation_flow.is_finite()) {
+                errors.push(format!(
+                    "signals.webster.{}.saturation_flow = {} must be greater than 0",
+                    name, approach.saturation_flow
+                ));
+            }
+        }
+        let reservation = &self.intersection.reservation;
+        if !(1..=16).contains(&reservation.grid) {
+            errors.push(format!(
+                "intersection.reservation.grid = {} must be between 1 and 16",
+                reservation.grid
+            ));
+        }
+        if reservation.request_distance < 0 {
+            errors.push(format!(
+                "intersection.reservation.request_distance = {} must not be negative",
+                reservation.request_distance
+            ));
+        }
+        // The island needs room inside the lane, and the roundabout room in the window
+        if self.intersection.control == ControlMode::Roundabout {
+            let radius = self.intersection.roundabout.radius;
+            let max_radius =
+                self.window.width.min(self.window.height) as i32 / 2 - self.road.width as i32;
+            if radius < self.road.width as i32 || radius > max_radius {
+                errors.push(format!(
+                    "intersection.roundabout.radius = {} must be between the road width ({}) and {}",
+                    radius, self.road.width, max_radius
+                ));
+            }
+        }
+        if self.signals.flash.from.is_some() != self.signals.flash.until.is_some() {
+            errors.push("signals.flash needs both from and until, or neither".to_string());
+        }
+        for (name, approach) in [
+            ("northbound", &self.demand.northbound),
+            ("southbound", &self.demand.southbound),
+            ("eastbound", &self.demand.eastbound),
+            ("westbound", &self.demand.westbound),
+        ] {
+            approach.validate(name, &mut errors);
+        }
+        for (i, interval) in self.demand.profile.iter().enumerate() {
+            let volumes = [
+                interval.northbound,
+                interval.southbound,
+                interval.eastbound,
+                interval.westbound,
+            ];
+            if volumes.iter().any(|v| !(*v >= 0.0 && v.is_finite())) {
+                errors.push(format!(
+                    "demand profile interval at {} has a negative volume",
+                    String::from(interval.time)
+                ));
+            }
+            if i > 0 && interval.time <= self.demand.profile[i - 1].time {
+                errors.push(format!(
+                    "demand profile intervals must be in time order ({} follows {})",
+                    String::from(interval.time),
+                    String::from(self.demand.profile[i - 1].time)
+                ));
+            }
+        }
+        // Banned U-turns leave an approach only its other movements
+        let intersection = &self.intersection;
+        for (name, direction) in [
+            ("northbound", Direction::North),
+            ("southbound", Direction::South),
+            ("eastbound", Direction::East),
+            ("westbound", Direction::West),
+        ] {
+            let approach = self.demand.approach(direction);
+            let has_volume = approach.vehicles_per_hour > 0.0
+                || self
+                    .demand
+                    .profile
+                    .iter()
+                    .any(|i| i.volume(direction) > 0.0);
+            if has_volume
+                && self.demand.od.is_empty()
+                && ROUTES
+                    .iter()
+                    .map(|&route| approach.share(route, |r| intersection.serves(direction, r)))
+                    .sum::<f64>()
+                    <= 0.0
+            {
+                errors.push(format!(
+                    "demand.{} has no turning proportion for a movement allowed there",
+                    name
+                ));
+            }
+        }
+        if !self.demand.od.is_empty() && !self.demand.profile.is_empty() {
+            errors.push("demand.od and demand.profile can't be used together".to_string());
+        }
+        for (i, row) in self.demand.od.iter().enumerate() {
+            let name = row.from.name();
+            let volumes = [row.north, row.south, row.east, row.west];
+            if volumes.iter().any(|v| !(*v >= 0.0 && v.is_finite())) {
+                errors.push(format!("demand OD row from {} has a negative volume", name));
+            }
+            // Leaving by the arm a vehicle came in on is a U-turn
+            if self.intersection.no_u_turn.contains(&row.from) && row.to(row.from.leaving()) != 0.0
+            {
+                errors.push(format!(
+                    "demand OD row from {} must have 0 to {} (U-turns are banned there)",
+                    name, name
+                ));
+            }
+            if self.demand.od[..i]
+                .iter()
+                .any(|other| other.from == row.from)
+            {
+                errors.push(format!("demand OD has more than one row from {}", name));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    pub fn spawn_cooldown_ticks(&self) -> u64 {
+        ticks_from_ms(self.vehicles.spawn_cooldown_ms)
+    }
+
+    pub fn min_green_ticks(&self) -> u64 {
+        ticks_from_ms(self.signals.min_green_ms)
+    }
+
+    pub fn max_green_ticks(&self) -> u64 {
+        ticks_from_ms(self.signals.max_green_ms)
+    }
+
+    pub fn max_wait_ticks(&self) -> u64 {
+        ticks_from_ms(self.signals.max_wait_ms)
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        let width = self.window.width as i32;
+        let height = self.window.height as i32;
+        let road_width = self.road.width as i32;
+        let half_road = road_width / 2;
+        let lane_offset = road_width / 4;
+        // At a roundabout vehicles stop where their lane meets the outside of
+        // the circulating road
+        let stop_line = match self.intersection.control {
+            ControlMode::Roundabout => {
+                let outer = (self.intersection.roundabout.radius + half_road / 2) as f64;
+                (outer * outer - (lane_offset * lane_offset) as f64).sqrt() as i32
+            }
+            _ => half_road,
+        };
+        Geometry {
+            width,
+            height,
+            center_x: width / 2,
+            center_y: height / 2,
+            half_road,
+            lane_offset,
+            stop_line,
+            drive_on: self.road.drive_on,
+        }
+    }
+}
+
+// Screen-space layout of the intersection, derived from the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub width: i32,
+    pub height: i32,
+    pub center_x: i32,
+    pub center_y: i32,
+    pub half_road: i32,
+    pub lane_offset: i32, // From the centre line to the middle of a lane
+    pub stop_line: i32,   // From the centre to the stop lines
+    pub drive_on: DriveOn,
+}
+
+impl Geometry {
+    // Which side of the centre line a vehicle's lane is on, looking the way
+    // it travels: 1 on the left, -1 on the right
+    pub fn lane_side(&self) -> i32 {
+        match self.drive_on {
+            DriveOn::Left => 1,
+            DriveOn::Right => -1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).expect("test config should parse")
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn shipped_config_is_valid() {
+        let config = Config::load(Path::new("config.toml")).expect("config.toml should load");
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn partial_config_keeps_other_defaults() {
+        let config = parse("[vehicles]\nspeed = 3\n");
+        assert_eq!(config.vehicles.speed, 3);
+        assert_eq!(config.vehicles.min_distance, 50);
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_error_in_one_message() {
+        let config = parse(
+            "[road]\n\
+             turn_offset = 100\n\
+             [vehicles]\n\
+             speed = 9\n\
+             speed_spread = 0.8\n\
+             [signals]\n\
+             min_green_ms = 5000\n\
+             max_green_ms = 4000\n\
+             [demand.northbound]\n\
+             vehicles_per_hour = -5\n",
+        );
+        let errors = config.validate().expect_err("config should be invalid");
+        for expected in [
+            "road.turn_offset = 100 must be between 0 and half the road width (35)",
+            "vehicles.speed = 9 must be between 1 and 5 pixels per tick",
+            "vehicles.speed_spread = 0.8 must be between 0 and 0.5",
+            "signals.min_green_ms = 5000 must be at most max_green_ms (4000)",
+            "demand.northbound.vehicles_per_hour = -5 must be a non-negative number",
+        ] {
+            assert!(
+                errors.contains(expected),
+                "missing \"{}\" in: {}",
+                expected,
+                errors
+            );
+        }
+        assert_eq!(errors.split("; ").count(), 5, "{}", errors);
+    }
+
+    #[test]
+    fn roundabout_radius_only_checked_at_a_roundabout() {
+        let config = parse("[road]\nwidth = 100\n");
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = parse("[road]\nwidth = 100\n[intersection]\ncontrol = \"roundabout\"\n");
+        assert_eq!(
+            config.validate(),
+            Err(
+                "intersection.roundabout.radius = 70 must be between the road width (100) and 300"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn approach_errors_name_their_table() {
+        let config = parse("[demand.westbound]\nstraight = 0\nleft = 0\nright = 0\n");
+        assert_eq!(
+            config.validate(),
+            Err("demand.westbound: straight, left, right and u_turn must be non-negative and not all 0"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn parses_clock_times() {
+        let parse = |text: &str| ClockTime::try_from(text.to_string());
+        assert_eq!(parse("00:00"), Ok(ClockTime(0)));
+        assert_eq!(parse("07:30"), Ok(ClockTime(450)));
+        assert_eq!(parse("7:05"), Ok(ClockTime(425)));
+        assert_eq!(parse("23:59"), Ok(ClockTime(1439)));
+        for bad in ["24:00", "12:60", "12", "12:", ":30", "-1:00", "ab:cd", ""] {
+            assert!(parse(bad).is_err(), "{} should be rejected", bad);
+        }
+        assert_eq!(String::from(ClockTime(425)), "07:05");
+    }
+
+    #[test]
+    fn time_of_day_runs_from_the_start_time_and_wraps() {
+        let demand = parse("[demand]\nstart_time = \"23:30\"\n").demand;
+        let minute = 60 * TICKS_PER_SECOND as u64;
+        assert_eq!(demand.time_of_day(0), ClockTime(23 * 60 + 30));
+        assert_eq!(
+            demand.time_of_day(29 * minute + minute - 1),
+            ClockTime(23 * 60 + 59)
+        );
+        assert_eq!(demand.time_of_day(30 * minute), ClockTime(0));
+        assert_eq!(
+            demand.time_of_day(24 * 60 * minute),
+            ClockTime(23 * 60 + 30)
+        );
+    }
+
+    #[test]
+    fn profile_interval_applies_from_its_time_until_the_next() {
+        let demand = parse(
+            "[demand]\n\
+             start_time = \"00:00\"\n\
+             [[demand.profile]]\n\
+             time = \"06:00\"\n\
+             northbound = 100\nsouthbound = 0\neastbound = 0\nwestbound = 0\n\
+             [[demand.profile]]\n\
+             time = \"09:00\"\n\
+             northbound = 200\nsouthbound = 0\neastbound = 0\nwestbound = 0\n\
+             [[demand.profile]]\n\
+             time = \"22:00\"\n\
+             northbound = 50\nsouthbound = 0\neastbound = 0\nwestbound = 0\n",
+        )
+        .demand;
+        let at = |hours: u64, minutes: u64| {
+            let tick = (hours * 60 + minutes) * 60 * TICKS_PER_SECOND as u64;
+            demand.vehicles_per_hour(Direction::North, tick)
+        };
+        assert_eq!(at(6, 0), 100.0);
+        assert_eq!(at(8, 59), 100.0);
+        assert_eq!(at(9, 0), 200.0);
+        assert_eq!(at(21, 59), 200.0);
+        assert_eq!(at(22, 0), 50.0);
+        // Before the first interval the last one is still running from the night before
+        assert_eq!(at(0, 0), 50.0);
+        assert_eq!(at(5, 59), 50.0);
+        assert_eq!(demand.vehicles_per_hour(Direction::South, 0), 0.0);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("[vehicles]\nsped = 3\n").is_err());
+        assert!(toml::from_str::<Config>("[lights]\n").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::config::{
+    ApproachDemand, ArrivalProcess, DemandConfig, IntersectionConfig, ticks_from_ms,
+};
+use crate::{Direction, Route};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+// Arrival state per approach, in a fixed order so runs stay deterministic
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Demand {
+    approaches: Vec<(Direction, ApproachState)>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct ApproachState {
+    next_arrival: Option<u64>, // Tick of the next vehicle, None while the approach has no volume
+    platoon_left: u32,         // Vehicles still to come in the current platoon
+    vehicles_per_hour: f64,    // Volume the next arrival was drawn for
+}
+
+impl Demand {
+    pub fn new() -> Self {
+        Demand {
+            approaches: [
+                Direction::North,
+                Direction::South,
+                Direction::East,
+                Direction::West,
+            ]
+            .into_iter()
+            .map(|direction| (direction, ApproachState::default()))
+            .collect(),
+        }
+    }
+
+    // Vehicles due this tick, with routes drawn from each approach's turning proportions
+    pub fn arrivals(
+        &mut self,
+        config: &DemandConfig,
+        intersection: &IntersectionConfig,
+        tick: u64,
+        rng: &mut ChaCha8Rng,
+    ) -> Vec<(Direction, Route)> {
+        let mut arrivals = Vec::new();
+        for (direction, state) in self.approaches.iter_mut() {
+            let demand = config.approach(*direction);
+            let vehicles_per_hour = config.vehicles_per_hour(*direction, tick);
+            // A new volume (e.g. the next profile interval) takes effect straight
+            // away rather than after the gap drawn for the old one
+            if vehicles_per_hour != state.vehicles_per_hour {
+                *state = ApproachState {
+                    vehicles_per_hour,
+                    ..ApproachState::default()
+                };
+            }
+            if vehicles_per_hour <= 0.0 {
+                continue;
+            }
+
+            let next_arrival = match state.next_arrival {
+                Some(next_arrival) => next_arrival,
+                None => tick + next_gap(demand, state, rng),
+            };
+            if tick >= next_arrival {
+                let route = config.route(*direction, rng.r#gen(), |route| {
+                    intersection.serves(*direction, route)
+                });
+                arrivals.push((*direction, route));
+                state.next_arrival = Some(tick + next_gap(demand, state, rng));
+            } else {
+                state.next_arrival = Some(next_arrival);
+            }
+        }
+        arrivals
+    }
+}
+
+// Ticks until the approach's next vehicle
+fn next_gap(demand: &ApproachDemand, state: &mut ApproachState, rng: &mut ChaCha8Rng) -> u64 {
+    let mean_gap = ApproachDemand::mean_gap_ticks(state.vehicles_per_hour);
+    let gap = match demand.process {
+        ArrivalProcess::Poisson => exponential(rng, mean_gap),
+        ArrivalProcess::Uniform => mean_gap,
+        ArrivalProcess::Platoon => {
+            let headway = ticks_from_ms(demand.platoon_headway_ms) as f64;
+            if state.platoon_left > 0 {
+                state.platoon_left -= 1;
+                headway
+            } else {
+                // Platoons start often enough to keep the configured mean volume
+                let size = demand.platoon_size as f64;
+                state.platoon_left = demand.platoon_size - 1;
+                exponential(rng, (mean_gap * size - headway * (size - 1.0)).max(1.0))
+            }
+        }
+    };
+    (gap.round() as u64).max(1)
+}
+
+fn exponential(rng: &mut ChaCha8Rng, mean: f64) -> f64 {
+    // gen() is in [0, 1), so the logarithm's argument is never 0
+    -mean * (1.0 - rng.r#gen::<f64>()).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TICKS_PER_SECOND;
+    use crate::config::Config;
+    use rand::SeedableRng;
+
+    #[test]
+    fn profile_volume_starts_at_its_interval() {
+        let config: Config = toml::from_str(
+            "[demand]\n\
+             start_time = \"08:00\"\n\
+             [demand.northbound]\n\
+             process = \"uniform\"\n\
+             [[demand.profile]]\n\
+             time = \"08:00\"\n\
+             northbound = 0\nsouthbound = 0\neastbound = 0\nwestbound = 0\n\
+             [[demand.profile]]\n\
+             time = \"08:01\"\n\
+             northbound = 3600\nsouthbound = 0\neastbound = 0\nwestbound = 0\n",
+        )
+        .unwrap();
+        let mut demand = Demand::new();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let minute = 60 * TICKS_PER_SECOND as u64;
+        let arrival_ticks: Vec<u64> = (0..minute + 4 * TICKS_PER_SECOND as u64)
+            .filter(|&tick| {
+                !demand
+                    .arrivals(&config.demand, &config.intersection, tick, &mut rng)
+                    .is_empty()
+            })
+            .collect();
+        // One vehicle a second, the first a full gap after the volume comes on
+        let second = TICKS_PER_SECOND as u64;
+        assert_eq!(
+            arrival_ticks,
+            vec![minute + second, minute + 2 * second, minute + 3 * second]
+        );
+    }
+}
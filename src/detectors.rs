@@ -0,0 +1,87 @@
+use crate::config::{DetectorConfig, Geometry};
+use crate::{Direction, Vehicle};
+use serde::{Deserialize, Serialize};
+
+// Virtual loop detector readings per approach, refreshed every tick
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Detectors {
+    approaches: Vec<(Direction, DetectorReading)>,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DetectorReading {
+    pub presence: bool,              // A vehicle is in the zone behind the stop line
+    pub passage: bool,               // A vehicle is over the upstream passage detector
+    pub last_actuation: Option<u64>, // Last tick either detector was occupied
+    pub count: u64,                  // Vehicles counted by the passage detector so far
+}
+
+impl Detectors {
+    pub fn new() -> Self {
+        Detectors {
+            approaches: [
+                Direction::North,
+                Direction::South,
+                Direction::East,
+                Direction::West,
+            ]
+            .into_iter()
+            .map(|direction| (direction, DetectorReading::default()))
+            .collect(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        vehicles: &[Vehicle],
+        g: &Geometry,
+        config: &DetectorConfig,
+        tick: u64,
+    ) {
+        for (direction, reading) in self.approaches.iter_mut() {
+            let was_passage = reading.passage;
+            reading.presence = false;
+            reading.passage = false;
+            for vehicle in vehicles {
+                if vehicle.direction != *direction || vehicle.has_passed_intersection {
+                    continue;
+                }
+                let distance = vehicle.distance_to_stop_line(g);
+                if (0..config.presence_length).contains(&distance) {
+                    reading.presence = true;
+                }
+                let passage_start = config.passage_distance;
+                if (passage_start..passage_start + config.passage_length).contains(&distance) {
+                    reading.passage = true;
+                }
+            }
+            // Gaps between vehicles are longer than the detector, so each
+            // occupation is one vehicle
+            if reading.passage && !was_passage {
+                reading.count += 1;
+            }
+            if reading.presence || reading.passage {
+                reading.last_actuation = Some(tick);
+            }
+        }
+    }
+
+    // Sets an approach's presence detector directly, as a vehicle over it would
+    #[cfg(test)]
+    pub fn set_presence(&mut self, direction: Direction, occupied: bool, tick: u64) {
+        if let Some((_, reading)) = self.approaches.iter_mut().find(|(d, _)| *d == direction) {
+            reading.presence = occupied;
+            if occupied {
+                reading.last_actuation = Some(tick);
+            }
+        }
+    }
+
+    pub fn reading(&self, direction: Direction) -> DetectorReading {
+        self.approaches
+            .iter()
+            .find(|(d, _)| *d == direction)
+            .map(|(_, reading)| *reading)
+            .unwrap_or_default()
+    }
+}
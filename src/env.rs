@@ -0,0 +1,469 @@
+use crate::cli::{EnvArgs, SimArgs};
+use crate::config::ControllerKind;
+use crate::session::Session;
+use crate::{Direction, Input, TICKS_PER_SECOND, TrafficSystem, resolve_config};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+// Per-approach observation values come in this order (direction of travel)
+const APPROACHES: [Direction; 4] = [
+    Direction::North,
+    Direction::South,
+    Direction::East,
+    Direction::West,
+];
+
+// What the reward measures over a step. All are to be maximised, so the
+// delay and queue rewards are negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RewardKind {
+    // Minus the vehicle-seconds spent standing still during the step
+    Delay,
+    // Minus the vehicles queued at the end of the step
+    Queue,
+    // Vehicles that left the intersection during the step
+    Throughput,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Observation {
+    pub tick: u64,
+    pub queues: [u32; 4], // Vehicles waiting to cross, including the entry queue
+    pub waiting_s: [f64; 4], // Time those vehicles have spent waiting so far
+    pub phase: Option<Direction>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepResult {
+    pub observation: Observation,
+    pub reward: f64,
+    pub done: bool,
+}
+
+// Reinforcement-learning environment over the headless simulation. The agent
+// picks the green approach every `ticks_per_step` ticks through the external
+// controller; an episode ends after `episode_ticks`.
+pub struct Env {
+    args: EnvArgs,
+    session: Option<Session>,
+    episode_start: u64,
+}
+
+impl Env {
+    pub fn new(args: EnvArgs) -> Result<Self, String> {
+        if args.output.log_events {
+            return Err("--log-events would mix with the protocol on stdout".to_string());
+        }
+        if args.ticks_per_step == 0 {
+            return Err("--ticks-per-step must be at least 1".to_string());
+        }
+        Ok(Env {
+            args,
+            session: None,
+            episode_start: 0,
+        })
+    }
+
+    // Starts a new episode, with the given seed or the one from the command line
+    pub fn reset(&mut self, seed: Option<u64>) -> Result<Observation, String> {
+        if let Some(session) = self.session.take() {
+            session.finish()?;
+        }
+        // The command-line seed stays in args for later unseeded resets
+        let sim = SimArgs {
+            seed: seed.or(self.args.sim.seed),
+            ..self.args.sim.clone()
+        };
+        let (mut config, mut start_from) = resolve_config(&sim)?;
+        config.signals.controller = ControllerKind::External;
+        if let Some(snapshot) = start_from.as_mut() {
+            snapshot.config.signals.controller = ControllerKind::External;
+        }
+        let session = Session::start(&sim, &self.args.output, config, start_from)?;
+        self.episode_start = session.system.tick;
+        Ok(observe(&self.session.insert(session).system))
+    }
+
+    // Shows the green to `phase` (None for all red) and simulates one step
+    pub fn step(&mut self, phase: Option<Direction>) -> Result<StepResult, String> {
+        let Some(session) = self.session.as_mut() else {
+            return Err("send reset before the first step".to_string());
+        };
+        let episode_end = self.episode_start + self.args.episode_ticks;
+        if session.system.tick >= episode_end {
+            return Err("the episode is over, send reset".to_string());
+        }
+
+        session.apply(Input::SetPhase(phase));
+        let delay_before = total_delay_ticks(&session.system);
+        let exited_before = session.system.metrics.vehicles_exited;
+        // Pace random spawns the way run does
+        let spawn_interval = session.system.config.spawn_cooldown_ticks().max(1);
+        for _ in 0..self.args.ticks_per_step {
+            if session.system.tick >= episode_end {
+                break;
+            }
+            if session.random_spawns() && session.system.tick % spawn_interval == 0 {
+                session.apply(Input::SpawnRandom);
+            }
+            session.step()?;
+            session.flush_events()?;
+        }
+
+        let system = &session.system;
+        let observation = observe(system);
+        let reward = match self.args.reward {
+            // Negated as integers so an empty intersection scores 0 rather than -0
+            RewardKind::Delay => {
+                -((total_delay_ticks(system) - delay_before) as i64) as f64
+                    / TICKS_PER_SECOND as f64
+            }
+            RewardKind::Queue => -(observation.queues.iter().sum::<u32>() as i64) as f64,
+            RewardKind::Throughput => (system.metrics.vehicles_exited - exited_before) as f64,
+        };
+        Ok(StepResult {
+            observation,
+            reward,
+            done: system.tick >= episode_end,
+        })
+    }
+
+    pub fn close(mut self) -> Result<(), String> {
+        if let Some(session) = self.session.take() {
+            session.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn observe(system: &TrafficSystem) -> Observation {
+    let g = system.config.geometry();
+    let mut queues = [0; 4];
+    let mut waiting_ticks = [0; 4];
+    for (i, &direction) in APPROACHES.iter().enumerate() {
+        for vehicle in system.vehicles.iter().filter(|v| v.direction == direction) {
+            if vehicle.is_queued(&g) {
+                queues[i] += 1;
+            }
+            if !vehicle.has_passed_intersection {
+                waiting_ticks[i] += vehicle.wait_ticks;
+            }
+        }
+        for (d, queue) in &system.entry_queues {
+            if *d == direction {
+                queues[i] += queue.len() as u32;
+                waiting_ticks[i] += queue.iter().map(|q| system.tick - q.since).sum::<u64>();
+            }
+        }
+    }
+    Observation {
+        tick: system.tick,
+        queues,
+        waiting_s: waiting_ticks.map(|ticks| ticks as f64 / TICKS_PER_SECOND as f64),
+        phase: system.external_green,
+    }
+}
+
+// Standing-still and entry-queue time so far, over vehicles that have left as
+// well as those still in the simulation
+fn total_delay_ticks(system: &TrafficSystem) -> u64 {
+    let metrics = &system.metrics;
+    let exited = metrics.total_wait_ticks + metrics.total_entry_delay_ticks;
+    let on_road: u64 = system.vehicles.iter().map(|v| v.wait_ticks).sum();
+    let queued: u64 = system
+        .entry_queues
+        .iter()
+        .flat_map(|(_, queue)| queue.iter())
+        .map(|q| system.tick - q.since)
+        .sum();
+    exited + on_road + queued
+}
+
+// One JSON request per line on stdin, one JSON reply per line on stdout:
+//   {"cmd": "reset", "seed": 7}       -> {"observation": {...}}
+//   {"cmd": "step", "action": "East"} -> {"observation": {...}, "reward": -3.2, "done": false}
+//   {"cmd": "close"}                  -> ends the session
+// A null action shows all red. Failed requests get {"error": "..."}.
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    Reset {
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    Step {
+        action: Option<Direction>,
+    },
+    Close,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Reply {
+    Reset { observation: Observation },
+    Step(StepResult),
+    Error { error: String },
+}
+
+pub fn serve(mut env: Env, input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("Failed to read request: {}", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Reset { seed }) => env
+                .reset(seed)
+                .map(|observation| Reply::Reset { observation }),
+            Ok(Request::Step { action }) => env.step(action).map(Reply::Step),
+            Ok(Request::Close) => break,
+            Err(e) => Err(format!("Failed to parse request: {}", e)),
+        }
+        .unwrap_or_else(|error| Reply::Error { error });
+        let reply = serde_json::to_string(&reply)
+            .map_err(|e| format!("Failed to serialize reply: {}", e))?;
+        writeln!(output, "{}", reply)
+            .and_then(|_| output.flush())
+            .map_err(|e| format!("Failed to write reply: {}", e))?;
+    }
+    env.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Route;
+    use crate::cli::{Cli, Command};
+    use crate::config::Config;
+    use clap::Parser;
+    use serde_json::{Value, json};
+
+    fn env_args(args: &[&str]) -> EnvArgs {
+        let cli = Cli::try_parse_from(["road_intersection", "env"].iter().chain(args))
+            .expect("test arguments should parse");
+        let Some(Command::Env(args)) = cli.command else {
+            unreachable!("parsed as another command");
+        };
+        args
+    }
+
+    fn env(args: &[&str]) -> Env {
+        Env::new(env_args(args)).expect("test arguments should be valid")
+    }
+
+    // Runs a whole session and returns the replies
+    fn talk(env: Env, requests: &[&str]) -> Vec<Value> {
+        let mut output = Vec::new();
+        serve(env, requests.join("\n").as_bytes(), &mut output).expect("session should run");
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("replies should be JSON"))
+            .collect()
+    }
+
+    const STEPS: [&str; 5] = [
+        r#"{"cmd": "step", "action": "North"}"#,
+        r#"{"cmd": "step", "action": null}"#,
+        r#"{"cmd": "step", "action": "East"}"#,
+        r#"{"cmd": "step", "action": "East"}"#,
+        r#"{"cmd": "step", "action": "South"}"#,
+    ];
+
+    // Steps through an episode after the given reset, with enough time for
+    // random vehicles to show up
+    fn episode(args: &[&str], reset: &str) -> Vec<Value> {
+        let mut requests = vec![reset];
+        requests.extend(STEPS);
+        talk(env(args), &requests)
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_episode() {
+        let args = ["--ticks-per-step", "300"];
+        let first = episode(&args, r#"{"cmd": "reset", "seed": 7}"#);
+        assert_eq!(first, episode(&args, r#"{"cmd": "reset", "seed": 7}"#));
+        assert_ne!(first, episode(&args, r#"{"cmd": "reset", "seed": 8}"#));
+        assert!(
+            first
+                .iter()
+                .any(|reply| reply["observation"]["queues"] != json!([0, 0, 0, 0])),
+            "no vehicles arrived: {:?}",
+            first
+        );
+    }
+
+    #[test]
+    fn reset_seed_overrides_the_command_line_one() {
+        let seeded = episode(
+            &["--ticks-per-step", "300"],
+            r#"{"cmd": "reset", "seed": 7}"#,
+        );
+        // Without a seed the reset falls back to --seed
+        let from_args = ["--ticks-per-step", "300", "--seed", "7"];
+        assert_eq!(seeded, episode(&from_args, r#"{"cmd": "reset"}"#));
+        let other_args = ["--ticks-per-step", "300", "--seed", "3"];
+        assert_eq!(
+            seeded,
+            episode(&other_args, r#"{"cmd": "reset", "seed": 7}"#)
+        );
+
+        // A seeded reset doesn't replace --seed for the next unseeded one
+        let mut requests = vec![r#"{"cmd": "reset", "seed": 7}"#, r#"{"cmd": "reset"}"#];
+        requests.extend(STEPS);
+        let replies = talk(env(&other_args), &requests);
+        assert_eq!(
+            replies[1..],
+            episode(&other_args, r#"{"cmd": "reset"}"#)[..]
+        );
+    }
+
+    #[test]
+    fn observation_lists_approaches_in_order() {
+        let mut config = Config::default();
+        config.signals.controller = ControllerKind::External;
+        let mut system = TrafficSystem::new(config, 1);
+        assert_eq!(
+            serde_json::to_value(observe(&system)).unwrap(),
+            json!({
+                "tick": 0,
+                "queues": [0, 0, 0, 0],
+                "waiting_s": [0.0, 0.0, 0.0, 0.0],
+                "phase": null,
+            })
+        );
+
+        // All red: one vehicle waits on the eastbound approach
+        system.spawn_vehicle_on_route(Direction::East, Route::Straight);
+        for _ in 0..600 {
+            system.update();
+        }
+        let observation = observe(&system);
+        assert_eq!(observation.queues, [0, 0, 1, 0]);
+        assert!(observation.waiting_s[2] > 0.0);
+        assert_eq!(observation.waiting_s[..2], [0.0, 0.0]);
+        assert_eq!(observation.waiting_s[3], 0.0);
+
+        system.apply(Input::SetPhase(Some(Direction::East)));
+        assert_eq!(observe(&system).phase, Some(Direction::East));
+    }
+
+    #[test]
+    fn rewards_have_the_documented_sign() {
+        // Ten seconds of all red, then ten of green for each approach in turn
+        let steps: Vec<String> = ["null", "\"North\"", "\"South\"", "\"East\"", "\"West\""]
+            .iter()
+            .flat_map(|action| vec![format!(r#"{{"cmd": "step", "action": {}}}"#, action); 10])
+            .collect();
+        let mut requests = vec![r#"{"cmd": "reset", "seed": 7}"#];
+        requests.extend(steps.iter().map(String::as_str));
+        let rewards = |reward: &str| -> Vec<f64> {
+            talk(env(&["--reward", reward]), &requests)[1..]
+                .iter()
+                .map(|reply| {
+                    reply["reward"]
+                        .as_f64()
+                        .expect("steps should have a reward")
+                })
+                .collect()
+        };
+
+        let delay = rewards("delay");
+        assert!(delay.iter().all(|&reward| reward <= 0.0), "{:?}", delay);
+        assert!(delay[..10].iter().sum::<f64>() < 0.0, "{:?}", delay);
+
+        let replies = talk(env(&["--reward", "queue"]), &requests);
+        for reply in &replies[1..] {
+            let queued: i64 = reply["observation"]["queues"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|queue| queue.as_i64().unwrap())
+                .sum();
+            assert_eq!(reply["reward"].as_f64(), Some(-queued as f64));
+        }
+
+        let throughput = rewards("throughput");
+        assert!(throughput.iter().all(|&reward| reward >= 0.0));
+        assert_eq!(throughput[..10].iter().sum::<f64>(), 0.0);
+        assert!(throughput.iter().sum::<f64>() > 0.0, "{:?}", throughput);
+    }
+
+    #[test]
+    fn episode_is_done_after_episode_ticks() {
+        let step = r#"{"cmd": "step", "action": "North"}"#;
+        let replies = talk(
+            env(&["--ticks-per-step", "50", "--episode-ticks", "120"]),
+            &[
+                r#"{"cmd": "reset"}"#,
+                step,
+                step,
+                step,
+                step,
+                r#"{"cmd": "reset"}"#,
+                step,
+            ],
+        );
+        let ticks_and_done: Vec<_> = replies[1..4]
+            .iter()
+            .map(|reply| (reply["observation"]["tick"].clone(), reply["done"].clone()))
+            .collect();
+        // The last step stops short at the end of the episode
+        assert_eq!(
+            ticks_and_done,
+            [
+                (json!(50), json!(false)),
+                (json!(100), json!(false)),
+                (json!(120), json!(true)),
+            ]
+        );
+        assert_eq!(
+            replies[4],
+            json!({"error": "the episode is over, send reset"})
+        );
+        assert_eq!(replies[5]["observation"]["tick"], json!(0));
+        assert_eq!(replies[6]["done"], json!(false));
+    }
+
+    #[test]
+    fn bad_requests_get_an_error_and_the_session_goes_on() {
+        let replies = talk(
+            env(&[]),
+            &[
+                r#"{"cmd": "step", "action": "North"}"#,
+                "not json",
+                r#"{"cmd": "jump"}"#,
+                r#"{"cmd": "step", "action": "Up"}"#,
+                "",
+                r#"{"cmd": "reset", "seed": 1}"#,
+                r#"{"cmd": "step", "action": "Up"}"#,
+                r#"{"cmd": "close"}"#,
+                r#"{"cmd": "reset"}"#,
+            ],
+        );
+        // The blank line gets no reply, and nothing is read after close
+        assert_eq!(replies.len(), 6, "{:?}", replies);
+        assert_eq!(
+            replies[0],
+            json!({"error": "send reset before the first step"})
+        );
+        for reply in [&replies[1], &replies[2], &replies[3], &replies[5]] {
+            let error = reply["error"].as_str().expect("should be an error reply");
+            assert!(error.starts_with("Failed to parse request: "), "{}", error);
+        }
+        assert_eq!(replies[4]["observation"]["tick"], json!(0));
+    }
+
+    #[test]
+    fn rejects_settings_that_break_the_protocol() {
+        let parse = |args: &[&str]| Env::new(env_args(args)).err();
+        assert_eq!(
+            parse(&["--log-events"]),
+            Some("--log-events would mix with the protocol on stdout".to_string())
+        );
+        assert_eq!(
+            parse(&["--ticks-per-step", "0"]),
+            Some("--ticks-per-step must be at least 1".to_string())
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use crate::{Direction, Route, VehicleId};
+
+// Something that happened to a vehicle during a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VehicleEvent {
+    pub tick: u64,
+    pub vehicle: VehicleId,
+    pub kind: VehicleEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VehicleEventKind {
+    Spawned {
+        direction: Direction,
+        route: Route,
+    },
+    ArrivedAtStopLine {
+        direction: Direction,
+    },
+    EnteredIntersection {
+        direction: Direction,
+    },
+    Turned {
+        from: Direction,
+        to: Direction,
+    },
+    Exited {
+        direction: Direction,
+        wait_ticks: u64,
+    },
+}
+
+impl VehicleEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VehicleEventKind::Spawned { .. } => "Spawned",
+            VehicleEventKind::ArrivedAtStopLine { .. } => "ArrivedAtStopLine",
+            VehicleEventKind::EnteredIntersection { .. } => "EnteredIntersection",
+            VehicleEventKind::Turned { .. } => "Turned",
+            VehicleEventKind::Exited { .. } => "Exited",
+        }
+    }
+
+    // Direction the vehicle is travelling in once the event has happened
+    pub fn direction(&self) -> Direction {
+        match *self {
+            VehicleEventKind::Spawned { direction, .. }
+            | VehicleEventKind::ArrivedAtStopLine { direction }
+            | VehicleEventKind::EnteredIntersection { direction }
+            | VehicleEventKind::Exited { direction, .. } => direction,
+            VehicleEventKind::Turned { to, .. } => to,
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use crate::events::VehicleEvent;
+use crate::{Direction, Route, TICKS_PER_SECOND, TrafficLightState, TrafficSystem, VehicleId};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Output formats selectable with --export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TrajectoryRecord {
+    tick: u64,
+    id: VehicleId,
+    x: i32,
+    y: i32,
+    direction: Direction,
+    route: Route,
+}
+
+#[derive(Serialize)]
+struct LightRecord {
+    tick: u64,
+    direction: Direction,
+    state: TrafficLightState,
+    entry_queue: usize, // Vehicles waiting off-screen on this approach
+}
+
+#[derive(Serialize)]
+struct EventRecord {
+    tick: u64,
+    id: VehicleId,
+    event: &'static str,
+    direction: Direction, // Direction of travel after the event
+}
+
+#[derive(Serialize)]
+struct SummaryRecord {
+    ticks: u64,
+    duration_s: f64,
+    vehicles_spawned: u32,
+    vehicles_exited: u32,
+    mean_travel_time_s: f64,
+    mean_wait_time_s: f64,
+    max_wait_time_s: f64,
+    mean_stop_line_delay_s: f64,
+    longest_stop_line_delay_s: f64,
+    exited_north: u32,
+    exited_south: u32,
+    exited_east: u32,
+    exited_west: u32,
+    mean_entry_delay_s: f64,
+    vehicles_delayed_at_entry: u32,
+    max_entry_queue: u32,
+    entry_queue_at_end: usize,
+    phase_switches: u32,
+}
+
+// Streams records to disk so long runs don't have to be kept in memory.
+// JSON files are written as a single array of objects.
+enum RecordWriter {
+    Csv(Box<csv::Writer<File>>),
+    Json { out: BufWriter<File>, first: bool },
+}
+
+impl RecordWriter {
+    fn create(path: &Path, format: ExportFormat) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        match format {
+            ExportFormat::Csv => Ok(RecordWriter::Csv(Box::new(csv::Writer::from_writer(file)))),
+            ExportFormat::Json => {
+                let mut out = BufWriter::new(file);
+                out.write_all(b"[").map_err(|e| e.to_string())?;
+                Ok(RecordWriter::Json { out, first: true })
+            }
+        }
+    }
+
+    fn write<T: Serialize>(&mut self, record: &T) -> Result<(), String> {
+        match self {
+            RecordWriter::Csv(writer) => writer.serialize(record).map_err(|e| e.to_string()),
+            RecordWriter::Json { out, first } => {
+                let separator: &[u8] = if *first { b"\n" } else { b",\n" };
+                *first = false;
+                out.write_all(separator).map_err(|e| e.to_string())?;
+                serde_json::to_writer(out, record).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self {
+            RecordWriter::Csv(mut writer) => writer.flush().map_err(|e| e.to_string()),
+            RecordWriter::Json { mut out, .. } => {
+                out.write_all(b"\n]\n").map_err(|e| e.to_string())?;
+                out.flush().map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+// Writes trajectories, light states, vehicle events and summary statistics for a run
+pub struct Exporter {
+    format: ExportFormat,
+    trajectories: RecordWriter,
+    lights: RecordWriter,
+    events: RecordWriter,
+    summary_path: PathBuf,
+}
+
+impl Exporter {
+    pub fn new(output_dir: &Path, format: ExportFormat) -> Result<Self, String> {
+        fs::create_dir_all(output_dir)
+            .map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+        let file_path = |name: &str| output_dir.join(format!("{}.{}", name, format.extension()));
+
+        Ok(Exporter {
+            format,
+            trajectories: RecordWriter::create(&file_path("trajectories"), format)?,
+            lights: RecordWriter::create(&file_path("lights"), format)?,
+            events: RecordWriter::create(&file_path("events"), format)?,
+            summary_path: file_path("summary"),
+        })
+    }
+
+    pub fn record_tick(&mut self, system: &TrafficSystem) -> Result<(), String> {
+        for vehicle in &system.vehicles {
+            self.trajectories.write(&TrajectoryRecord {
+                tick: system.tick,
+                id: vehicle.id,
+                x: vehicle.position.x,
+                y: vehicle.position.y,
+                direction: vehicle.direction,
+                route: vehicle.route,
+            })?;
+        }
+        for light in &system.traffic_lights {
+            self.lights.write(&LightRecord {
+                tick: system.tick,
+                direction: light.direction,
+                state: light.state,
+                entry_queue: system.entry_queue_len(light.direction),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn record_events(&mut self, events: &[VehicleEvent]) -> Result<(), String> {
+        for event in events {
+            self.events.write(&EventRecord {
+                tick: event.tick,
+                id: event.vehicle,
+                event: event.kind.name(),
+                direction: event.kind.direction(),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self, system: &TrafficSystem) -> Result<(), String> {
+        self.trajectories.finish()?;
+        self.lights.finish()?;
+        self.events.finish()?;
+
+        let metrics = &system.metrics;
+        let seconds = |ticks: f64| ticks / TICKS_PER_SECOND as f64;
+        let summary = SummaryRecord {
+            ticks: system.tick,
+            duration_s: seconds(system.tick as f64),
+            vehicles_spawned: metrics.vehicles_spawned,
+            vehicles_exited: metrics.vehicles_exited,
+            mean_travel_time_s: seconds(metrics.mean_travel_ticks()),
+            mean_wait_time_s: seconds(metrics.mean_wait_ticks()),
+            max_wait_time_s: seconds(metrics.max_wait_ticks as f64),
+            mean_stop_line_delay_s: seconds(metrics.mean_stop_line_delay_ticks()),
+            longest_stop_line_delay_s: seconds(
+                metrics.longest_stop_line_delay_ticks(system.tick) as f64
+            ),
+            exited_north: metrics.exited_towards(Direction::North),
+            exited_south: metrics.exited_towards(Direction::South),
+            exited_east: metrics.exited_towards(Direction::East),
+            exited_west: metrics.exited_towards(Direction::West),
+            mean_entry_delay_s: seconds(metrics.mean_entry_delay_ticks()),
+            vehicles_delayed_at_entry: metrics.vehicles_delayed_at_entry,
+            max_entry_queue: metrics.max_entry_queue,
+            entry_queue_at_end: [
+                Direction::North,
+                Direction::South,
+                Direction::East,
+                Direction::West,
+            ]
+            .into_iter()
+            .map(|direction| system.entry_queue_len(direction))
+            .sum(),
+            phase_switches: metrics.phase_switches,
+        };
+
+        match self.format {
+            ExportFormat::Csv => {
+                let mut writer = RecordWriter::create(&self.summary_path, self.format)?;
+                writer.write(&summary)?;
+                writer.finish()
+            }
+            ExportFormat::Json => {
+                // The summary is a single object rather than an array
+                let file = File::create(&self.summary_path).map_err(|e| {
+                    format!("Failed to create {}: {}", self.summary_path.display(), e)
+                })?;
+                let mut out = BufWriter::new(file);
+                serde_json::to_writer_pretty(&mut out, &summary).map_err(|e| e.to_string())?;
+                out.write_all(b"\n").map_err(|e| e.to_string())?;
+                out.flush().map_err(|e| e.to_string())
+            }
+        }
+    }
+}
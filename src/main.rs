@@ -1,31 +1,66 @@
+mod actuated;
+mod cli;
+mod config;
+mod demand;
+mod detectors;
+mod env;
+mod events;
+mod export;
+mod max_pressure;
+mod metrics;
+mod render;
+mod replay;
+mod reservation;
+mod roundabout;
+mod scenario;
+mod session;
+mod snapshot;
+mod speed;
+mod stop_control;
+mod webster;
+
+use actuated::ActuatedController;
+use clap::Parser;
+use cli::{Cli, Command, EnvArgs, PlayArgs, ReplayArgs, RunArgs, SimArgs};
+use config::{Config, ControlMode, ControllerKind, DriveOn, FlashMode, Geometry};
+use demand::Demand;
+use detectors::Detectors;
+use events::{VehicleEvent, VehicleEventKind};
+use max_pressure::MaxPressureController;
+use metrics::Metrics;
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use render::Renderer;
+use replay::{Replay, ReplayPlayer};
+use reservation::ReservationManager;
+use roundabout::Roundabout;
 use sdl2::event::Event;
-use sdl2::image::{InitFlag, LoadTexture};
+use sdl2::image::InitFlag;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::{Point, Rect};
-use sdl2::render::{Canvas, Texture, TextureCreator};
-use sdl2::video::{Window, WindowContext};
-use std::collections::HashMap;
+use sdl2::rect::Point;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use serde::{Deserialize, Serialize};
+use session::{Session, print_summary};
+use speed::SpeedLimits;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
-use std::time::{Duration, Instant};
-// Constants
-const WINDOW_WIDTH: u32 = 900;
-const WINDOW_HEIGHT: u32 = 800;
-const ROAD_WIDTH: u32 = 70;
+use std::time::Duration;
+use stop_control::{RightOfWay, StopControl};
+use webster::WebsterController;
+// Constants (tunable values live in Config)
 const VEHICLE_WIDTH: u32 = 25;
 const VEHICLE_HEIGHT: u32 = 35;
-const VEHICLE_SPEED: i32 = 2;
+// Pixels trimmed off each side of a sprite when checking what a vehicle
+// covers, so cars that only graze each other or the box edge don't count
+const SPRITE_INSET: i32 = 6;
 const TRAFFIC_LIGHT_SIZE: u32 = 20;
-const MIN_VEHICLE_DISTANCE: i32 = 50;
-const VEHICLE_SPAWN_COOLDOWN: Duration = Duration::from_millis(1000);
 const TRAFFIC_LIGHT_POS_OFFSET: i32 = 20;
-const MAX_GREEN_TIME: Duration = Duration::from_secs(4);
-const TURN_OFFSET: i32 = 30;
-const NUMBER_OF_CARS_FOR_PRIORITY: u32 = 4;
+const TICKS_PER_SECOND: u32 = 60;
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
 
 // Directions
-#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Serialize, Deserialize)]
 enum Direction {
     North,
     South,
@@ -34,310 +69,873 @@ enum Direction {
 }
 
 // Route types
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 enum Route {
     Straight,
     Left,
     Right,
+    UTurn,
+}
+
+impl Direction {
+    // Direction of travel after taking a route through the intersection
+    fn after(self, route: Route) -> Direction {
+        match (self, route) {
+            (direction, Route::Straight) => direction,
+            (Direction::North, Route::UTurn) => Direction::South,
+            (Direction::South, Route::UTurn) => Direction::North,
+            (Direction::East, Route::UTurn) => Direction::West,
+            (Direction::West, Route::UTurn) => Direction::East,
+            (Direction::North, Route::Left) | (Direction::South, Route::Right) => Direction::West,
+            (Direction::North, Route::Right) | (Direction::South, Route::Left) => Direction::East,
+            (Direction::East, Route::Left) | (Direction::West, Route::Right) => Direction::North,
+            (Direction::East, Route::Right) | (Direction::West, Route::Left) => Direction::South,
+        }
+    }
+
+    // Direction seen in a mirror along the north-south centre line
+    fn mirrored(self) -> Direction {
+        match self {
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+            direction => direction,
+        }
+    }
+}
+
+impl Route {
+    fn mirrored(self) -> Route {
+        match self {
+            Route::Left => Route::Right,
+            Route::Right => Route::Left,
+            route => route,
+        }
+    }
 }
 
 // Traffic light state
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 enum TrafficLightState {
     Red,
     Green,
+    FlashingRed,    // Stop, then proceed when clear
+    FlashingYellow, // Proceed with caution
+    Dark,           // Failed; treated as a stop sign
 }
+#[derive(Clone, Serialize, Deserialize)]
 struct TrafficLight {
+    #[serde(with = "snapshot::point")]
     position: Point,
     state: TrafficLightState,
     direction: Direction,
-    last_change: Instant,
+    last_change: u64,                  // Tick of the last state update
+    forced: Option<TrafficLightState>, // Held by a scenario regardless of the controller
 }
 
+// Stable vehicle identifier, handed out in spawn order and never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+struct VehicleId(u32);
+
+#[derive(Clone, Serialize, Deserialize)]
 struct Vehicle {
+    id: VehicleId,
+    #[serde(with = "snapshot::point")]
     position: Point,
     direction: Direction,
     route: Route,
     // color: Color,
     has_turned: bool,
     has_passed_intersection: bool,
+    arrived_at_stop_line: bool,
+    entered_intersection: bool,
+    wait_ticks: u64,         // Ticks spent standing still
+    circulated: Option<i32>, // Distance driven round the roundabout, while on it
+    desire: f64,             // Speed the driver wants to go, as a multiple of the limit
+    carry: f64,              // Part of a pixel driven but not yet moved
+}
+
+impl Vehicle {
+    // The vehicle as it would be on the left-hand layout, which driving on
+    // the right mirrors
+    fn on_left(&self, g: &Geometry) -> Vehicle {
+        let mut vehicle = self.clone();
+        if g.drive_on == DriveOn::Right {
+            vehicle.mirror(g);
+        }
+        vehicle
+    }
+
+    // How far the vehicle still has to go before the point where it would stop
+    // for a red light; negative once it has moved past
+    fn distance_to_stop_line(&self, g: &Geometry) -> i32 {
+        let Vehicle {
+            position,
+            direction,
+            ..
+        } = self.on_left(g);
+        match direction {
+            Direction::North => position.y - ((g.center_y + g.stop_line) - 5),
+            Direction::South => (g.center_y - g.stop_line - VEHICLE_HEIGHT as i32) - position.y,
+            Direction::East => ((g.center_x - g.stop_line) - 5 - VEHICLE_WIDTH as i32) - position.x,
+            Direction::West => position.x - ((g.center_x + g.stop_line) - 5),
+        }
+    }
+
+    // Within the few pixels where it stops for a red light
+    fn at_stop_line(&self, g: &Geometry) -> bool {
+        let Vehicle {
+            position,
+            direction,
+            ..
+        } = self.on_left(g);
+        match direction {
+            Direction::North => {
+                let stop_y = (g.center_y + g.stop_line) - 5;
+                position.y >= stop_y && position.y <= stop_y + 5
+            }
+            Direction::South => {
+                let stop_y = g.center_y - g.stop_line;
+                position.y >= stop_y - VEHICLE_HEIGHT as i32
+                    && position.y <= stop_y - VEHICLE_HEIGHT as i32 + 5
+            }
+            Direction::East => {
+                let stop_x = (g.center_x - g.stop_line) - 5;
+                position.x >= stop_x - VEHICLE_WIDTH as i32
+                    && position.x <= stop_x - VEHICLE_WIDTH as i32 + 5
+            }
+            Direction::West => {
+                let stop_x = (g.center_x + g.stop_line) - 5;
+                position.x >= stop_x && position.x <= stop_x + 5
+            }
+        }
+    }
+
+    // Still on its approach, short of the stop line; counted as waiting for a green
+    fn is_queued(&self, g: &Geometry) -> bool {
+        if self.has_passed_intersection || self.circulated.is_some() {
+            return false;
+        }
+        let Vehicle {
+            position,
+            direction,
+            ..
+        } = self.on_left(g);
+        match direction {
+            Direction::North => position.y >= (g.center_y + g.stop_line) - 5,
+            Direction::South => position.y <= g.center_y - g.stop_line,
+            Direction::East => position.x <= (g.center_x - g.stop_line) - 5,
+            Direction::West => position.x >= (g.center_x + g.stop_line) - 5,
+        }
+    }
+
+    // Moves one tick along the route: marks the vehicle as past the
+    // intersection, makes the turn once it gets to the turning point, then
+    // drives on at the given speed
+    fn advance(&mut self, g: &Geometry, speed: i32, turn_offset: i32) {
+        // Driving on the right is driving on the left seen in a mirror
+        if g.drive_on == DriveOn::Right {
+            self.mirror(g);
+            self.advance_on_left(g, speed, turn_offset);
+            self.mirror(g);
+        } else {
+            self.advance_on_left(g, speed, turn_offset);
+        }
+    }
+
+    // Reflects the vehicle along the north-south centre line, which swaps
+    // east for west and left for right. The sprite is as wide across the
+    // screen as the vehicle is long when it travels east or west; halving
+    // and doubling keeps mirrored lanes on the lanes vehicles spawn in.
+    fn mirror(&mut self, g: &Geometry) {
+        let footprint = match self.direction {
+            Direction::North | Direction::South => VEHICLE_WIDTH as i32,
+            Direction::East | Direction::West => VEHICLE_HEIGHT as i32,
+        };
+        self.position.x = 2 * g.center_x - self.position.x - 2 * (footprint / 2);
+        self.direction = self.direction.mirrored();
+        self.route = self.route.mirrored();
+    }
+
+    fn advance_on_left(&mut self, g: &Geometry, speed: i32, turn_offset: i32) {
+        let intersection_center_x = g.center_x;
+        let intersection_center_y = g.center_y;
+        if !self.has_passed_intersection {
+            match self.direction {
+                Direction::North => {
+                    if (self.route == Route::Right
+                        && self.position.y <= intersection_center_y - turn_offset)
+                        || ((self.route == Route::Left
+                            || self.route == Route::Straight
+                            || self.route == Route::UTurn)
+                            && self.position.y <= intersection_center_y)
+                    {
+                        self.has_passed_intersection = true;
+                    }
+                }
+                Direction::South => {
+                    if self.position.y >= intersection_center_y - turn_offset {
+                        self.has_passed_intersection = true;
+                    }
+                }
+                Direction::East => {
+                    if ((self.route == Route::Right || self.route == Route::UTurn)
+                        && self.position.x >= intersection_center_x)
+                        || ((self.route == Route::Left || self.route == Route::Straight)
+                            && self.position.x >= intersection_center_x - turn_offset)
+                    {
+                        self.has_passed_intersection = true;
+                    }
+                }
+                Direction::West => {
+                    if (self.route == Route::Left && self.position.x <= intersection_center_x)
+                        || ((self.route == Route::Right
+                            || self.route == Route::Straight
+                            || self.route == Route::UTurn)
+                            && self.position.x <= intersection_center_x - turn_offset)
+                    {
+                        self.has_passed_intersection = true;
+                    }
+                }
+            }
+        }
+
+        if self.has_passed_intersection && !self.has_turned {
+            match (self.direction, self.route) {
+                (Direction::North, Route::Left) if self.position.y <= intersection_center_y => {
+                    self.direction = Direction::West;
+                    self.position = Point::new(
+                        intersection_center_x - g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                        intersection_center_y + g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                    );
+                    self.has_turned = true;
+                }
+                (Direction::North, Route::Right) if self.position.y <= intersection_center_y => {
+                    self.direction = Direction::East;
+                    self.position = Point::new(
+                        intersection_center_x - g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                        intersection_center_y - g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                    );
+                    self.has_turned = true;
+                }
+                (Direction::South, Route::Left) => {
+                    let stop_y = (g.center_x - g.half_road) - 5;
+                    if self.position.y + VEHICLE_WIDTH as i32 >= stop_y - 5 {
+                        self.direction = Direction::East;
+                        self.position = Point::new(
+                            intersection_center_x + g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                            intersection_center_y - g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                        );
+                        self.has_turned = true;
+                    }
+                }
+                (Direction::South, Route::Right) if self.position.y >= intersection_center_y => {
+                    self.direction = Direction::West;
+                    self.position = Point::new(
+                        intersection_center_x + g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                        intersection_center_y + g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                    );
+                    self.has_turned = true;
+                }
+                (Direction::East, Route::Left) => {
+                    let stop_x = (g.center_x - g.half_road) - 5;
+                    if self.position.x + VEHICLE_WIDTH as i32 >= stop_x - 5 {
+                        self.direction = Direction::North;
+                        self.position = Point::new(
+                            intersection_center_x - g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                            intersection_center_y - g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                        );
+                        self.has_turned = true;
+                    }
+                }
+                (Direction::East, Route::Right) => {
+                    let stop_x = (g.center_x - g.half_road) - 5;
+                    if self.position.x + VEHICLE_WIDTH as i32 >= stop_x - 5 {
+                        self.direction = Direction::South;
+                        self.position = Point::new(
+                            intersection_center_x + g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                            intersection_center_y - g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                        );
+                        self.has_turned = true;
+                    }
+                }
+                (Direction::West, Route::Left) if self.position.x <= intersection_center_x => {
+                    self.direction = Direction::South;
+                    self.position = Point::new(
+                        intersection_center_x + g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                        intersection_center_y + g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                    );
+                    self.has_turned = true;
+                }
+                (Direction::West, Route::Right) if self.position.x <= intersection_center_x => {
+                    self.direction = Direction::North;
+                    self.position = Point::new(
+                        intersection_center_x - g.lane_offset - VEHICLE_WIDTH as i32 / 2,
+                        intersection_center_y + g.lane_offset - VEHICLE_HEIGHT as i32 / 2,
+                    );
+                    self.has_turned = true;
+                }
+                // U-turns carry on to the far lane of the box, then come back
+                // down the other side of the road
+                (Direction::North, Route::UTurn)
+                    if self.position.y
+                        <= intersection_center_y - g.lane_offset - VEHICLE_HEIGHT as i32 / 2 =>
+                {
+                    self.direction = Direction::South;
+                    self.position.x =
+                        intersection_center_x + g.lane_offset - VEHICLE_WIDTH as i32 / 2;
+                    self.has_turned = true;
+                }
+                (Direction::South, Route::UTurn)
+                    if self.position.y
+                        >= intersection_center_y + g.lane_offset - VEHICLE_HEIGHT as i32 / 2 =>
+                {
+                    self.direction = Direction::North;
+                    self.position.x =
+                        intersection_center_x - g.lane_offset - VEHICLE_WIDTH as i32 / 2;
+                    self.has_turned = true;
+                }
+                (Direction::East, Route::UTurn)
+                    if self.position.x
+                        >= intersection_center_x + g.lane_offset - VEHICLE_HEIGHT as i32 / 2 =>
+                {
+                    self.direction = Direction::West;
+                    self.position.y =
+                        intersection_center_y + g.lane_offset - VEHICLE_HEIGHT as i32 / 2;
+                    self.has_turned = true;
+                }
+                (Direction::West, Route::UTurn)
+                    if self.position.x
+                        <= intersection_center_x - g.lane_offset - VEHICLE_HEIGHT as i32 / 2 =>
+                {
+                    self.direction = Direction::East;
+                    self.position.y =
+                        intersection_center_y - g.lane_offset - VEHICLE_HEIGHT as i32 / 2;
+                    self.has_turned = true;
+                }
+                _ => {}
+            }
+        }
+        self.drive(speed);
+    }
+
+    // Whether the vehicle has to stop to keep min_distance behind one at
+    // `position` travelling the same way in its lane
+    fn too_close_behind(&self, position: Point, direction: Direction, min_distance: i32) -> bool {
+        if self.direction != direction {
+            return false;
+        }
+        match self.direction {
+            Direction::North => {
+                self.position.x == position.x
+                    && self.position.y > position.y
+                    && self.position.y - position.y - (VEHICLE_HEIGHT as i32) < min_distance
+            }
+            Direction::South => {
+                self.position.x == position.x
+                    && self.position.y < position.y
+                    && position.y - self.position.y - (VEHICLE_HEIGHT as i32) < min_distance
+            }
+            Direction::East => {
+                self.position.y == position.y
+                    && self.position.x < position.x
+                    && position.x - self.position.x - (VEHICLE_WIDTH as i32) < min_distance
+            }
+            Direction::West => {
+                self.position.y == position.y
+                    && self.position.x > position.x
+                    && self.position.x - position.x - (VEHICLE_WIDTH as i32) < min_distance
+            }
+        }
+    }
+
+    // Whole pixels to move this tick at the given speed; the fraction left
+    // over is moved later, so positions stay on the pixel grid
+    fn step(&mut self, speed: f64) -> i32 {
+        let distance = speed + self.carry;
+        let pixels = distance.floor();
+        self.carry = distance - pixels;
+        pixels as i32
+    }
+
+    // Moves straight ahead
+    fn drive(&mut self, speed: i32) {
+        match self.direction {
+            Direction::North => self.position.y -= speed,
+            Direction::South => self.position.y += speed,
+            Direction::East => self.position.x += speed,
+            Direction::West => self.position.x -= speed,
+        }
+    }
+}
+
+// Demand waiting off-screen for space at its approach's entry
+#[derive(Clone, Serialize, Deserialize)]
+struct QueuedVehicle {
+    route: Route,
+    since: u64, // Tick the vehicle joined the queue
 }
 
-struct TrafficSystem<'a> {
+// Player and test inputs that change the simulation; recorded for replays
+#[derive(Clone, Serialize, Deserialize)]
+enum Input {
+    Spawn(Direction),
+    SpawnRandom,
+    Restore(Box<TrafficSystem>), // Loading a snapshot replaces the whole state
+    SpawnOnRoute(Direction, Route),
+    CloseApproach(Direction),
+    OpenApproach(Direction),
+    ForceLight(Direction, Option<TrafficLightState>), // None hands the light back to the controller
+    SetPhase(Option<Direction>), // Green approach for the external controller, None for all red
+    SetFlash(Option<FlashMode>), // Overrides the flash schedule; None follows it again
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct TrafficSystem {
+    config: Config,
     vehicles: Vec<Vehicle>,
     traffic_lights: Vec<TrafficLight>,
-    last_spawn_time: Instant,
-    vehicle_textures: HashMap<(Direction, Route), Texture<'a>>, // Updated to store Direction and Route
-    traffic_light_textures: HashMap<TrafficLightState, Texture<'a>>,
+    last_spawn_ticks: HashMap<Direction, u64>, // Manual spawns, for the per-approach cooldown
+    tick: u64,
+    rng: ChaCha8Rng, // All randomness comes from here so runs are reproducible from the seed
+    next_vehicle_id: u32,
+    closed_approaches: Vec<Direction>, // No new vehicles enter on these
+    demand: Demand,
+    entry_queues: Vec<(Direction, VecDeque<QueuedVehicle>)>, // Fixed order keeps releases deterministic
+    detectors: Detectors,
+    actuated: ActuatedController,
+    max_pressure: MaxPressureController,
+    webster: WebsterController,
+    external_green: Option<Direction>, // Last phase set through Input::SetPhase
+    stop_control: StopControl,
+    flash_override: Option<FlashMode>, // Set from the keyboard or a scenario
+    reservations: ReservationManager,
+    #[serde(skip)]
+    events: Vec<VehicleEvent>, // Emitted since the last take_events
+    metrics: Metrics,
 }
 
-impl<'a> TrafficSystem<'a> {
-    fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Result<Self, String> {
-        let traffic_lights = vec![
+impl TrafficSystem {
+    fn new(config: Config, seed: u64) -> Self {
+        let g = config.geometry();
+        let mut traffic_lights = vec![
             TrafficLight {
                 position: Point::new(
-                    (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - TRAFFIC_LIGHT_POS_OFFSET,
-                    WINDOW_HEIGHT as i32 / 2 + ROAD_WIDTH as i32 / 2,
+                    (g.center_x - g.stop_line) - TRAFFIC_LIGHT_POS_OFFSET,
+                    g.center_y + g.stop_line,
                 ),
                 state: TrafficLightState::Red,
                 direction: Direction::North,
-                last_change: Instant::now(),
+                last_change: 0,
+                forced: None,
             },
             TrafficLight {
                 position: Point::new(
-                    WINDOW_WIDTH as i32 / 2 + ROAD_WIDTH as i32 / 2,
-                    (WINDOW_HEIGHT as i32 / 2 - ROAD_WIDTH as i32 / 2) - TRAFFIC_LIGHT_POS_OFFSET,
+                    g.center_x + g.stop_line,
+                    (g.center_y - g.stop_line) - TRAFFIC_LIGHT_POS_OFFSET,
                 ),
                 state: TrafficLightState::Red,
                 direction: Direction::South,
-                last_change: Instant::now(),
+                last_change: 0,
+                forced: None,
             },
             TrafficLight {
                 position: Point::new(
-                    (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - TRAFFIC_LIGHT_POS_OFFSET,
-                    (WINDOW_HEIGHT as i32 / 2 - ROAD_WIDTH as i32 / 2) - TRAFFIC_LIGHT_POS_OFFSET,
+                    (g.center_x - g.stop_line) - TRAFFIC_LIGHT_POS_OFFSET,
+                    (g.center_y - g.stop_line) - TRAFFIC_LIGHT_POS_OFFSET,
                 ),
                 state: TrafficLightState::Green,
                 direction: Direction::East,
-                last_change: Instant::now(),
+                last_change: 0,
+                forced: None,
             },
             TrafficLight {
-                position: Point::new(
-                    WINDOW_WIDTH as i32 / 2 + ROAD_WIDTH as i32 / 2,
-                    WINDOW_HEIGHT as i32 / 2 + ROAD_WIDTH as i32 / 2,
-                ),
+                position: Point::new(g.center_x + g.stop_line, g.center_y + g.stop_line),
                 state: TrafficLightState::Red,
                 direction: Direction::West,
-                last_change: Instant::now(),
+                last_change: 0,
+                forced: None,
             },
         ];
-
-        // Load vehicle textures for each Direction and Route combination
-        let mut vehicle_textures = HashMap::new();
-        let directions = [
-            Direction::North,
-            Direction::South,
-            Direction::East,
-            Direction::West,
-        ];
-        let routes = [Route::Straight, Route::Left, Route::Right];
-
-        for &direction in &directions {
-            for &route in &routes {
-                let texture_path = match (direction, route) {
-                    (Direction::North, Route::Straight) => "assets/vehicles/car_north_straight.png",
-                    (Direction::North, Route::Left) => "assets/vehicles/car_north_left.png",
-                    (Direction::North, Route::Right) => "assets/vehicles/car_north_right.png",
-                    (Direction::South, Route::Straight) => "assets/vehicles/car_south_straight.png",
-                    (Direction::South, Route::Left) => "assets/vehicles/car_south_left.png",
-                    (Direction::South, Route::Right) => "assets/vehicles/car_south_right.png",
-                    (Direction::East, Route::Straight) => "assets/vehicles/car_east_straight.png",
-                    (Direction::East, Route::Left) => "assets/vehicles/car_east_left.png",
-                    (Direction::East, Route::Right) => "assets/vehicles/car_east_right.png",
-                    (Direction::West, Route::Straight) => "assets/vehicles/car_west_straight.png",
-                    (Direction::West, Route::Left) => "assets/vehicles/car_west_left.png",
-                    (Direction::West, Route::Right) => "assets/vehicles/car_west_right.png",
-                };
-                vehicle_textures.insert(
-                    (direction, route),
-                    texture_creator
-                        .load_texture(Path::new(texture_path))
-                        .map_err(|e| format!("Failed to load {}: {}", texture_path, e))?,
-                );
+        // Driving on the right, each light stands where the mirror image of
+        // its approach has it, on the right-hand kerb
+        if g.drive_on == DriveOn::Right {
+            let left_hand: Vec<(Direction, Point)> = traffic_lights
+                .iter()
+                .map(|light| (light.direction, light.position))
+                .collect();
+            for light in traffic_lights.iter_mut() {
+                if let Some(&(_, position)) = left_hand
+                    .iter()
+                    .find(|(direction, _)| *direction == light.direction.mirrored())
+                {
+                    light.position = Point::new(
+                        2 * g.center_x - position.x - TRAFFIC_LIGHT_SIZE as i32,
+                        position.y,
+                    );
+                }
             }
         }
 
-        // Load traffic light textures
-        let mut traffic_light_textures = HashMap::new();
-        traffic_light_textures.insert(
-            TrafficLightState::Red,
-            texture_creator
-                .load_texture(Path::new("assets/traffic_lights/red.png"))
-                .map_err(|e| e.to_string())?,
-        );
-        traffic_light_textures.insert(
-            TrafficLightState::Green,
-            texture_creator
-                .load_texture(Path::new("assets/traffic_lights/green.png"))
-                .map_err(|e| e.to_string())?,
-        );
-
-        Ok(TrafficSystem {
+        TrafficSystem {
+            config,
             vehicles: Vec::new(),
             traffic_lights,
-            last_spawn_time: Instant::now(),
-            vehicle_textures,
-            traffic_light_textures,
-        })
+            last_spawn_ticks: HashMap::new(),
+            tick: 0,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            next_vehicle_id: 0,
+            closed_approaches: Vec::new(),
+            demand: Demand::new(),
+            entry_queues: [
+                Direction::North,
+                Direction::South,
+                Direction::East,
+                Direction::West,
+            ]
+            .into_iter()
+            .map(|direction| (direction, VecDeque::new()))
+            .collect(),
+            detectors: Detectors::new(),
+            actuated: ActuatedController::default(),
+            max_pressure: MaxPressureController::default(),
+            webster: WebsterController::default(),
+            external_green: None,
+            stop_control: StopControl::default(),
+            flash_override: None,
+            reservations: ReservationManager::default(),
+            events: Vec::new(),
+            metrics: Metrics::default(),
+        }
     }
-    fn update_traffic_lights(&mut self) {
-        // Count waiting vehicles per direction
-        let mut vehicle_counts = [
-            (Direction::North, 0),
-            (Direction::South, 0),
-            (Direction::East, 0),
-            (Direction::West, 0),
-        ];
-        for vehicle in &self.vehicles {
-            if !vehicle.has_passed_intersection {
-                match vehicle.direction {
-                    Direction::North => {
-                        let stop_y = (WINDOW_HEIGHT as i32 / 2 + ROAD_WIDTH as i32 / 2) - 5;
-                        if vehicle.position.y >= stop_y {
-                            vehicle_counts[0].1 += 1;
-                        }
-                    }
-                    Direction::South => {
-                        let stop_y = WINDOW_HEIGHT as i32 / 2 - ROAD_WIDTH as i32 / 2;
-                        if vehicle.position.y <= stop_y {
-                            vehicle_counts[1].1 += 1;
-                        }
-                    }
-                    Direction::East => {
-                        let stop_x = (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - 5;
-                        if vehicle.position.x <= stop_x {
-                            vehicle_counts[2].1 += 1;
-                        }
-                    }
-                    Direction::West => {
-                        let stop_x = (WINDOW_WIDTH as i32 / 2 + ROAD_WIDTH as i32 / 2) - 5;
-                        if vehicle.position.x >= stop_x {
-                            vehicle_counts[3].1 += 1;
-                        }
+
+    fn apply(&mut self, input: Input) {
+        match input {
+            Input::Spawn(direction) => self.spawn_vehicle(direction),
+            Input::SpawnRandom => self.spawn_random_vehicle(),
+            Input::Restore(snapshot) => *self = *snapshot,
+            Input::SpawnOnRoute(direction, route) => self.spawn_vehicle_on_route(direction, route),
+            Input::CloseApproach(direction) => {
+                if !self.closed_approaches.contains(&direction) {
+                    self.closed_approaches.push(direction);
+                }
+            }
+            Input::OpenApproach(direction) => self.closed_approaches.retain(|&d| d != direction),
+            Input::ForceLight(direction, state) => {
+                for light in self.traffic_lights.iter_mut() {
+                    if light.direction == direction {
+                        light.forced = state;
                     }
                 }
             }
+            Input::SetPhase(green) => self.external_green = green,
+            Input::SetFlash(mode) => self.flash_override = mode,
         }
+    }
 
-        // Sum total vehicles
-        let total_vehicles: u32 = vehicle_counts.iter().map(|&(_, count)| count).sum();
+    fn update(&mut self) {
+        self.release_entry_queues();
+        self.generate_arrivals();
+        if self.config.intersection.control == ControlMode::Signals {
+            self.update_traffic_lights();
+        }
+        self.stop_control.update(
+            &self.vehicles,
+            &self.config.geometry(),
+            &self.rights_of_way(),
+            &self.config.intersection,
+            &SpeedLimits::new(&self.config),
+            self.tick,
+        );
+        if self.config.intersection.control == ControlMode::Reservation {
+            self.reservations
+                .update(&self.vehicles, &self.config, self.tick);
+        }
+        self.update_vehicles();
+        self.tick += 1;
+    }
 
-        // Find current green light and check conditions
-        let mut current_green_idx = None;
-        let mut should_change = false;
-        let mut priority_direction = None;
-
-        // Check for priority condition: lane with >= 5 cars while another has < 3
-        for &(dir, count) in &vehicle_counts {
-            if count >= NUMBER_OF_CARS_FOR_PRIORITY {
-                // Check if there's a green light in another lane with < 3 cars
-                for light in &self.traffic_lights {
-                    if light.state == TrafficLightState::Green && light.direction != dir {
-                        let current_lane_count = vehicle_counts
-                            .iter()
-                            .find(|&&(d, _)| d == light.direction)
-                            .map(|&(_, count)| count)
-                            .unwrap_or(0);
-                        if current_lane_count < 3 {
-                            priority_direction = Some(dir);
-                            should_change = true;
-                            break;
-                        }
-                    }
-                }
-            }
+    fn emit(&mut self, vehicle: VehicleId, kind: VehicleEventKind) {
+        let event = VehicleEvent {
+            tick: self.tick,
+            vehicle,
+            kind,
+        };
+        self.metrics.record_event(&event);
+        self.events.push(event);
+    }
+
+    // Hands the events emitted since the previous call to the caller
+    fn take_events(&mut self) -> Vec<VehicleEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn update_traffic_lights(&mut self) {
+        let g = self.config.geometry();
+        self.detectors.update(
+            &self.vehicles,
+            &g,
+            &self.config.signals.detectors,
+            self.tick,
+        );
+
+        let flash = self.flash_mode();
+        if flash != FlashMode::Off {
+            self.set_flashing(flash);
+        } else {
+            self.run_controller(&g);
         }
 
-        // If no priority condition, find lane with most vehicles
-        if priority_direction.is_none() {
-            let mut max_vehicles = 0;
-            for &(dir, count) in &vehicle_counts {
-                if count > max_vehicles {
-                    max_vehicles = count;
-                    priority_direction = Some(dir);
-                }
+        for light in self.traffic_lights.iter_mut() {
+            if let Some(state) = light.forced {
+                light.state = state;
             }
+        }
+    }
 
-            // Check if current green light should change
-            for (i, light) in self.traffic_lights.iter().enumerate() {
-                if light.state == TrafficLightState::Green {
-                    current_green_idx = Some(i);
-                    let elapsed = light.last_change.elapsed();
-                    let current_dir = light.direction;
-                    let current_lane_vehicles = vehicle_counts
-                        .iter()
-                        .find(|&&(dir, _)| dir == current_dir)
-                        .map(|&(_, count)| count)
-                        .unwrap_or(0);
-
-                    // Change if max time reached or no vehicles in current lane
-                    should_change = elapsed >= MAX_GREEN_TIME || current_lane_vehicles == 0;
-                }
+    fn run_controller(&mut self, g: &Geometry) {
+        match self.config.signals.controller {
+            ControllerKind::Priority => self.update_priority_lights(),
+            ControllerKind::Actuated => {
+                let green =
+                    self.actuated
+                        .update(&self.detectors, &self.config.signals.actuated, self.tick);
+                self.set_green(green);
             }
+            ControllerKind::MaxPressure => {
+                let green = self.max_pressure.update(
+                    &self.vehicles,
+                    g,
+                    &self.config.signals.max_pressure,
+                    &self.config.intersection,
+                    self.tick,
+                );
+                self.set_green(green);
+            }
+            ControllerKind::Webster => {
+                let green =
+                    self.webster
+                        .update(&self.detectors, &self.config.signals.webster, self.tick);
+                self.set_green(green);
+            }
+            ControllerKind::External => self.set_green(self.external_green),
         }
+    }
 
-        if total_vehicles == 0 {
-            // Set all lights to red if no vehicles
-            for light in self.traffic_lights.iter_mut() {
-                light.state = TrafficLightState::Red;
-                light.last_change = Instant::now();
-            }
-        } else {
-            let next_idx = if let Some(idx) = current_green_idx {
-                if should_change {
-                    // Choose priority direction or direction with most vehicles
-                    let target_dir = priority_direction.unwrap_or(Direction::North);
-                    self.traffic_lights
-                        .iter()
-                        .position(|light| light.direction == target_dir)
-                        .unwrap_or((idx + 1) % 4)
-                } else {
-                    idx // Keep current green if no change needed
-                }
+    // The keyboard or a scenario overrides the configured schedule
+    fn flash_mode(&self) -> FlashMode {
+        self.flash_override.unwrap_or_else(|| {
+            let time = self.config.demand.time_of_day(self.tick);
+            self.config.signals.flash.scheduled(time)
+        })
+    }
+
+    fn set_flashing(&mut self, flash: FlashMode) {
+        let major_road = self.config.intersection.major_road;
+        for light in self.traffic_lights.iter_mut() {
+            let state = if flash == FlashMode::Yellow && major_road.carries(light.direction) {
+                TrafficLightState::FlashingYellow
             } else {
-                // No green light, choose priority direction or direction with vehicles
-                let target_dir = priority_direction.unwrap_or(Direction::North);
-                self.traffic_lights
+                TrafficLightState::FlashingRed
+            };
+            if light.state != state {
+                light.state = state;
+                light.last_change = self.tick;
+            }
+        }
+    }
+
+    // What each approach's sign or light currently tells vehicles to do
+    fn rights_of_way(&self) -> [(Direction, RightOfWay); 4] {
+        let intersection = &self.config.intersection;
+        [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ]
+        .map(|direction| {
+            let right = match intersection.control {
+                ControlMode::AllWayStop => RightOfWay::Stop,
+                ControlMode::Yield if intersection.major_road.carries(direction) => RightOfWay::Go,
+                ControlMode::Yield => RightOfWay::Yield,
+                // Reservations and the roundabout replace signs and lights alike
+                ControlMode::Reservation | ControlMode::Roundabout => RightOfWay::Blocked,
+                ControlMode::Signals => match self
+                    .traffic_lights
                     .iter()
-                    .position(|light| light.direction == target_dir)
-                    .unwrap_or(0)
+                    .find(|light| light.direction == direction)
+                    .map_or(TrafficLightState::Red, |light| light.state)
+                {
+                    TrafficLightState::Green | TrafficLightState::FlashingYellow => RightOfWay::Go,
+                    TrafficLightState::Red => RightOfWay::Blocked,
+                    TrafficLightState::FlashingRed | TrafficLightState::Dark => RightOfWay::Stop,
+                },
             };
+            (direction, right)
+        })
+    }
 
-            // Update lights
-            for (i, light) in self.traffic_lights.iter_mut().enumerate() {
-                if i == next_idx {
-                    light.state = TrafficLightState::Green;
-                    light.last_change = Instant::now();
-                } else {
-                    light.state = TrafficLightState::Red;
-                    light.last_change = Instant::now();
+    // Shows green to one approach (or none) and red to the rest
+    fn set_green(&mut self, green: Option<Direction>) {
+        for light in self.traffic_lights.iter_mut() {
+            let state = if Some(light.direction) == green {
+                TrafficLightState::Green
+            } else {
+                TrafficLightState::Red
+            };
+            if light.state != state {
+                // Forced lights are put back every tick, which isn't a switch
+                if state == TrafficLightState::Green && light.forced.is_none() {
+                    self.metrics.record_phase_switch();
                 }
+                light.state = state;
+                light.last_change = self.tick;
             }
         }
     }
-    fn spawn_vehicle(&mut self, direction: Direction) {
-        if self.last_spawn_time.elapsed() < VEHICLE_SPAWN_COOLDOWN {
+
+    // Vehicles on each approach that haven't reached the stop line yet
+    fn queue_counts(&self) -> [(Direction, u32); 4] {
+        let g = self.config.geometry();
+        let mut vehicle_counts = [
+            (Direction::North, 0),
+            (Direction::South, 0),
+            (Direction::East, 0),
+            (Direction::West, 0),
+        ];
+        for vehicle in self.vehicles.iter().filter(|v| v.is_queued(&g)) {
+            if let Some(count) = vehicle_counts
+                .iter_mut()
+                .find(|(direction, _)| *direction == vehicle.direction)
+            {
+                count.1 += 1;
+            }
+        }
+        vehicle_counts
+    }
+
+    // The approach whose queued vehicle has stood still longest, once that
+    // wait reaches the max wait bound; forced approaches can't be served
+    fn starved_approach(&self, forced: &[Direction]) -> Option<Direction> {
+        let max_wait = self.config.max_wait_ticks();
+        if max_wait == 0 {
+            return None;
+        }
+        let g = self.config.geometry();
+        self.vehicles
+            .iter()
+            .filter(|v| {
+                v.is_queued(&g) && v.wait_ticks >= max_wait && !forced.contains(&v.direction)
+            })
+            .max_by_key(|v| v.wait_ticks)
+            .map(|v| v.direction)
+    }
+
+    fn update_priority_lights(&mut self) {
+        // Forced lights don't follow the controller, so their approaches
+        // can't hold or be given the green
+        let forced: Vec<Direction> = self
+            .traffic_lights
+            .iter()
+            .filter(|light| light.forced.is_some())
+            .map(|light| light.direction)
+            .collect();
+        let current = self
+            .traffic_lights
+            .iter()
+            .find(|light| light.state == TrafficLightState::Green && light.forced.is_none())
+            .map(|light| (light.direction, self.tick - light.last_change));
+
+        // A new green always runs for the minimum green, starved approaches included
+        if let Some((_, elapsed)) = current
+            && elapsed < self.config.min_green_ticks()
+        {
             return;
         }
 
-        let can_spawn = match direction {
-            Direction::North => !self.vehicles.iter().any(|v| {
-                v.direction == Direction::North
-                    && v.position.y
-                        > WINDOW_HEIGHT as i32 - VEHICLE_HEIGHT as i32 - MIN_VEHICLE_DISTANCE
-            }),
-            Direction::South => !self
-                .vehicles
-                .iter()
-                .any(|v| v.direction == Direction::South && v.position.y < MIN_VEHICLE_DISTANCE),
-            Direction::East => !self
-                .vehicles
+        // Serve a starved approach before anything else, and keep it green
+        // until the vehicles over the bound have gone
+        if let Some(starved) = self.starved_approach(&forced) {
+            self.set_green(Some(starved));
+            return;
+        }
+
+        // Count waiting vehicles per direction
+        let mut vehicle_counts = self.queue_counts();
+        for (direction, count) in vehicle_counts.iter_mut() {
+            if forced.contains(direction) {
+                *count = 0;
+            }
+        }
+        let count_for = |direction: Direction| {
+            vehicle_counts
                 .iter()
-                .any(|v| v.direction == Direction::East && v.position.x < MIN_VEHICLE_DISTANCE),
-            Direction::West => !self.vehicles.iter().any(|v| {
-                v.direction == Direction::West
-                    && v.position.x
-                        > WINDOW_WIDTH as i32 - VEHICLE_WIDTH as i32 - MIN_VEHICLE_DISTANCE
-            }),
+                .find(|&&(d, _)| d == direction)
+                .map_or(0, |&(_, count)| count)
+        };
+        // Lane with most vehicles, ties going to the first in count order
+        let busiest = |except: Option<Direction>| {
+            let mut best: Option<(Direction, u32)> = None;
+            for &(direction, count) in &vehicle_counts {
+                if Some(direction) != except
+                    && count > 0
+                    && best.is_none_or(|(_, best_count)| count > best_count)
+                {
+                    best = Some((direction, count));
+                }
+            }
+            best.map(|(direction, _)| direction)
+        };
+
+        // Set all lights to red if no vehicles
+        let total_vehicles: u32 = vehicle_counts.iter().map(|&(_, count)| count).sum();
+        if total_vehicles == 0 {
+            self.set_green(None);
+            return;
+        }
+
+        let Some((green, elapsed)) = current else {
+            self.set_green(busiest(None));
+            return;
         };
+        let green_count = count_for(green);
+
+        // Priority condition: a lane with the threshold or more cars takes over a
+        // green lane with < 3, provided it leads by the switch margin so two
+        // busy lanes don't keep swapping the green
+        let signals = &self.config.signals;
+        let priority_direction = vehicle_counts
+            .iter()
+            .find(|&&(direction, count)| {
+                direction != green
+                    && count >= signals.priority_threshold
+                    && green_count < 3
+                    && count >= green_count + signals.switch_margin
+            })
+            .map(|&(direction, _)| direction);
+        if priority_direction.is_some() {
+            self.set_green(priority_direction);
+            return;
+        }
 
-        if !can_spawn {
+        // Otherwise change if max time reached or no vehicles in current lane,
+        // to the busiest of the others
+        if (elapsed >= self.config.max_green_ticks() || green_count == 0)
+            && let Some(next) = busiest(Some(green))
+        {
+            self.set_green(Some(next));
+        }
+    }
+    fn spawn_vehicle(&mut self, direction: Direction) {
+        let last_spawn = self.last_spawn_ticks.get(&direction).copied().unwrap_or(0);
+        if self.tick - last_spawn < self.config.spawn_cooldown_ticks() {
             return;
         }
 
-        let mut rng = rand::thread_rng();
+        // U-turns only come from the configured demand and scenarios
         let options = [Route::Straight, Route::Left, Route::Right];
-        let route = options[rng.gen_range(0..3)];
+        let route = options[self.rng.gen_range(0..3)];
 
         // let color = match route {
         //     Route::Straight => Color::RGB(0, 0, 255),
@@ -345,41 +943,179 @@ impl<'a> TrafficSystem<'a> {
         //     Route::Right => Color::RGB(255, 255, 0),
         // };
 
+        self.enqueue_vehicle(direction, route);
+        self.last_spawn_ticks.insert(direction, self.tick);
+    }
+
+    // Scripted spawns pick their own route and aren't held back by the spawn cooldown
+    fn spawn_vehicle_on_route(&mut self, direction: Direction, route: Route) {
+        self.enqueue_vehicle(direction, route);
+    }
+
+    // Every new vehicle joins its approach's entry queue and drives in as soon
+    // as there is room, so demand is held rather than dropped
+    fn enqueue_vehicle(&mut self, direction: Direction, route: Route) {
+        // Scripted U-turns are dropped where they're banned
+        if !self.config.intersection.serves(direction, route) {
+            return;
+        }
+        let since = self.tick;
+        let queue = self.entry_queue_mut(direction);
+        queue.push_back(QueuedVehicle { route, since });
+        let queued = queue.len() as u32;
+        self.metrics.record_entry_queue(queued);
+        self.release_entry_queue(direction);
+    }
+
+    fn entry_queue_mut(&mut self, direction: Direction) -> &mut VecDeque<QueuedVehicle> {
+        self.entry_queues
+            .iter_mut()
+            .find(|(d, _)| *d == direction)
+            .map(|(_, queue)| queue)
+            .expect("every direction has an entry queue")
+    }
+
+    // Vehicles waiting off-screen on an approach
+    fn entry_queue_len(&self, direction: Direction) -> usize {
+        self.entry_queues
+            .iter()
+            .find(|(d, _)| *d == direction)
+            .map_or(0, |(_, queue)| queue.len())
+    }
+
+    fn release_entry_queues(&mut self) {
+        for direction in [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ] {
+            self.release_entry_queue(direction);
+        }
+    }
+
+    // Lets the front of the queue in when the entry is free
+    fn release_entry_queue(&mut self, direction: Direction) {
+        if !self.can_spawn(direction) {
+            return;
+        }
+        if let Some(queued) = self.entry_queue_mut(direction).pop_front() {
+            self.metrics.record_entry_delay(self.tick - queued.since);
+            self.add_vehicle(direction, queued.route);
+        }
+    }
+
+    // The approach must be open and its entry clear of the previous vehicle
+    fn can_spawn(&self, direction: Direction) -> bool {
+        if self.closed_approaches.contains(&direction) {
+            return false;
+        }
+
+        let g = self.config.geometry();
+        let min_distance = self.config.vehicles.min_distance;
+        // Checked on the left-hand layout, which driving on the right mirrors
+        let (direction, vehicles) = match g.drive_on {
+            DriveOn::Left => (direction, self.vehicles.clone()),
+            DriveOn::Right => {
+                let mut vehicles = self.vehicles.clone();
+                vehicles.iter_mut().for_each(|v| v.mirror(&g));
+                (direction.mirrored(), vehicles)
+            }
+        };
+        match direction {
+            Direction::North => !vehicles.iter().any(|v| {
+                v.direction == Direction::North
+                    && v.position.y > g.height - VEHICLE_HEIGHT as i32 - min_distance
+            }),
+            Direction::South => !vehicles
+                .iter()
+                .any(|v| v.direction == Direction::South && v.position.y < min_distance),
+            Direction::East => !vehicles
+                .iter()
+                .any(|v| v.direction == Direction::East && v.position.x < min_distance),
+            Direction::West => !vehicles.iter().any(|v| {
+                v.direction == Direction::West
+                    && v.position.x > g.width - VEHICLE_WIDTH as i32 - min_distance
+            }),
+        }
+    }
+
+    fn add_vehicle(&mut self, direction: Direction, route: Route) {
+        let g = self.config.geometry();
+        // Placed on the left-hand layout and mirrored when driving on the right
+        let (direction, route) = match g.drive_on {
+            DriveOn::Left => (direction, route),
+            DriveOn::Right => (direction.mirrored(), route.mirrored()),
+        };
+        let lane_offset = g.lane_offset;
         let position = match direction {
             Direction::North => Point::new(
-                WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 4 - VEHICLE_WIDTH as i32 / 2,
-                WINDOW_HEIGHT as i32,
+                g.center_x - lane_offset - VEHICLE_WIDTH as i32 / 2,
+                g.height,
             ),
             Direction::South => Point::new(
-                WINDOW_WIDTH as i32 / 2 + ROAD_WIDTH as i32 / 4 - VEHICLE_WIDTH as i32 / 2,
+                g.center_x + lane_offset - VEHICLE_WIDTH as i32 / 2,
                 0 - VEHICLE_HEIGHT as i32,
             ),
             Direction::East => Point::new(
                 0 - VEHICLE_WIDTH as i32,
-                WINDOW_HEIGHT as i32 / 2 - ROAD_WIDTH as i32 / 4 - VEHICLE_HEIGHT as i32 / 2,
+                g.center_y - lane_offset - VEHICLE_HEIGHT as i32 / 2,
             ),
             Direction::West => Point::new(
-                WINDOW_WIDTH as i32,
-                WINDOW_HEIGHT as i32 / 2 + ROAD_WIDTH as i32 / 4 - VEHICLE_HEIGHT as i32 / 2,
+                g.width,
+                g.center_y + lane_offset - VEHICLE_HEIGHT as i32 / 2,
             ),
         };
 
-        let vehicle = Vehicle {
+        // Only drawn with a spread, so runs without one keep their random sequence
+        let spread = self.config.vehicles.speed_spread;
+        let desire = if spread > 0.0 {
+            speed::draw_desire(spread, &mut self.rng)
+        } else {
+            1.0
+        };
+
+        let id = VehicleId(self.next_vehicle_id);
+        let mut vehicle = Vehicle {
+            id,
             position,
             direction,
             route,
             // color,
             has_turned: false,
             has_passed_intersection: false,
+            arrived_at_stop_line: false,
+            entered_intersection: false,
+            wait_ticks: 0,
+            circulated: None,
+            desire,
+            carry: 0.0,
         };
+        if g.drive_on == DriveOn::Right {
+            vehicle.mirror(&g);
+        }
+        let (direction, route) = (vehicle.direction, vehicle.route);
 
         self.vehicles.push(vehicle);
-        self.last_spawn_time = Instant::now();
+        self.next_vehicle_id += 1;
+        self.emit(id, VehicleEventKind::Spawned { direction, route });
+    }
+
+    // Automatic demand from the configured arrival processes
+    fn generate_arrivals(&mut self) {
+        let arrivals = self.demand.arrivals(
+            &self.config.demand,
+            &self.config.intersection,
+            self.tick,
+            &mut self.rng,
+        );
+        for (direction, route) in arrivals {
+            self.spawn_vehicle_on_route(direction, route);
+        }
     }
 
     fn spawn_random_vehicle(&mut self) {
-        let mut rng = rand::thread_rng();
-        let direction = match rng.gen_range(0..4) {
+        let direction = match self.rng.gen_range(0..4) {
             0 => Direction::North,
             1 => Direction::South,
             2 => Direction::East,
@@ -389,20 +1125,64 @@ impl<'a> TrafficSystem<'a> {
     }
 
     fn update_vehicles(&mut self) {
-        let mut to_remove = Vec::new();
+        let mut exited = Vec::new();
+        let mut events = Vec::new();
         let vehicle_count = self.vehicles.len();
+        let g = self.config.geometry();
+        let limits = SpeedLimits::new(&self.config);
+        let min_distance = self.config.vehicles.min_distance;
+        let turn_offset = self.config.road.turn_offset;
+        let rights = self.rights_of_way();
+        let control = self.config.intersection.control;
+        let roundabout = Roundabout::new(
+            &g,
+            &self.config.intersection.roundabout,
+            limits.circulating(),
+            min_distance,
+        );
+        // Vehicles that may move on or into the roundabout, decided on where
+        // everyone was at the start of the tick
+        let roundabout_clear: Vec<VehicleId> = if control == ControlMode::Roundabout {
+            self.vehicles
+                .iter()
+                .filter(|v| match v.circulated {
+                    Some(_) => !roundabout.blocked(v, &self.vehicles),
+                    None => roundabout.entry_clear(v, &self.vehicles),
+                })
+                .map(|v| v.id)
+                .collect()
+        } else {
+            Vec::new()
+        };
 
         let vehicle_positions: Vec<_> = self
             .vehicles
             .iter()
             .map(|v| (v.position, v.direction, v.has_passed_intersection))
             .collect();
+        // Vehicles a vehicle at the stop line has to wait for: those past
+        // their own stop line on the way in, and those still in the box. An
+        // eastbound car waiting at its line already pokes into the box, so
+        // only vehicles that have moved off the line count. A U-turn that
+        // has come round runs with the opposing approach but still crosses it.
+        let mut box_users: Vec<(Direction, bool)> = self
+            .vehicles
+            .iter()
+            .filter(|v| {
+                if v.has_passed_intersection {
+                    reservation::area_covered(v, &g).is_some()
+                } else {
+                    v.distance_to_stop_line(&g) < 0 && !v.at_stop_line(&g)
+                }
+            })
+            .map(|v| (v.direction, v.route == Route::UTurn && v.has_turned))
+            .collect();
 
         // Define intersection bounds
-        let intersection_left = WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2;
-        let intersection_right = WINDOW_WIDTH as i32 / 2 + ROAD_WIDTH as i32 / 2;
-        let intersection_top = WINDOW_HEIGHT as i32 / 2 - ROAD_WIDTH as i32 / 2;
-        let intersection_bottom = WINDOW_HEIGHT as i32 / 2 + ROAD_WIDTH as i32 / 2;
+        let intersection_left = g.center_x - g.half_road;
+        let intersection_right = g.center_x + g.half_road;
+        let intersection_top = g.center_y - g.half_road;
+        let intersection_bottom = g.center_y + g.half_road;
 
         let is_in_intersection = |pos: Point| {
             let vehicle_center_x = pos.x + VEHICLE_WIDTH as i32 / 2;
@@ -417,390 +1197,297 @@ impl<'a> TrafficSystem<'a> {
             let vehicle = &mut self.vehicles[i];
 
             if vehicle.position.x < -100
-                || vehicle.position.x > WINDOW_WIDTH as i32 + 100
+                || vehicle.position.x > g.width + 100
                 || vehicle.position.y < -100
-                || vehicle.position.y > WINDOW_HEIGHT as i32 + 100
+                || vehicle.position.y > g.height + 100
             {
-                to_remove.push(i);
+                exited.push(vehicle.id);
+                events.push((
+                    vehicle.id,
+                    VehicleEventKind::Exited {
+                        direction: vehicle.direction,
+                        wait_ticks: vehicle.wait_ticks,
+                    },
+                ));
                 continue;
             }
 
-            let should_stop_for_vehicle = {
-                let mut stop = false;
-                for (j, (other_pos, other_dir, _)) in vehicle_positions.iter().enumerate() {
-                    if i == j {
-                        continue;
-                    }
-                    if vehicle.direction != *other_dir {
-                        continue;
-                    }
-                    match vehicle.direction {
-                        Direction::North => {
-                            if vehicle.position.x == other_pos.x
-                                && vehicle.position.y > other_pos.y
-                                && vehicle.position.y - other_pos.y - (VEHICLE_HEIGHT as i32)
-                                    < MIN_VEHICLE_DISTANCE
-                            {
-                                stop = true;
-                                break;
-                            }
-                        }
-                        Direction::South => {
-                            if vehicle.position.x == other_pos.x
-                                && vehicle.position.y < other_pos.y
-                                && other_pos.y - vehicle.position.y - (VEHICLE_HEIGHT as i32)
-                                    < MIN_VEHICLE_DISTANCE
-                            {
-                                stop = true;
-                                break;
-                            }
-                        }
-                        Direction::East => {
-                            if vehicle.position.y == other_pos.y
-                                && vehicle.position.x < other_pos.x
-                                && other_pos.x - vehicle.position.x - (VEHICLE_WIDTH as i32)
-                                    < MIN_VEHICLE_DISTANCE
-                            {
-                                stop = true;
-                                break;
-                            }
-                        }
-                        Direction::West => {
-                            if vehicle.position.y == other_pos.y
-                                && vehicle.position.x > other_pos.x
-                                && vehicle.position.x - other_pos.x - (VEHICLE_WIDTH as i32)
-                                    < MIN_VEHICLE_DISTANCE
-                            {
-                                stop = true;
-                                break;
-                            }
-                        }
+            // On the roundabout vehicles only follow the one ahead round the circle
+            if vehicle.circulated.is_some() {
+                if roundabout_clear.contains(&vehicle.id) {
+                    let direction_before_exit = vehicle.direction;
+                    let speed = vehicle.step(limits.free_flow(vehicle, &g));
+                    roundabout.drive(vehicle, speed);
+                    if vehicle.direction != direction_before_exit {
+                        events.push((
+                            vehicle.id,
+                            VehicleEventKind::Turned {
+                                from: direction_before_exit,
+                                to: vehicle.direction,
+                            },
+                        ));
                     }
+                } else {
+                    vehicle.wait_ticks += 1;
                 }
-                stop
-            };
+                continue;
+            }
+
+            let should_stop_for_vehicle =
+                vehicle_positions
+                    .iter()
+                    .enumerate()
+                    .any(|(j, &(other_pos, other_dir, _))| {
+                        i != j && vehicle.too_close_behind(other_pos, other_dir, min_distance)
+                    });
 
-            let light_state = self
-                .traffic_lights
+            let right = rights
                 .iter()
-                .find(|light| light.direction == vehicle.direction)
-                .map(|light| light.state)
-                .unwrap_or(TrafficLightState::Red);
+                .find(|(direction, _)| *direction == vehicle.direction)
+                .map_or(RightOfWay::Blocked, |&(_, right)| right);
+            let may_enter = match control {
+                ControlMode::Reservation => self.reservations.may_enter(vehicle.id),
+                ControlMode::Roundabout => roundabout_clear.contains(&vehicle.id),
+                _ => self.stop_control.may_enter(right, vehicle.id),
+            };
 
-            let at_stop_line = match vehicle.direction {
-                Direction::North => {
-                    let stop_y = (WINDOW_HEIGHT as i32 / 2 + ROAD_WIDTH as i32 / 2) - 5;
-                    vehicle.position.y >= stop_y && vehicle.position.y <= stop_y + 5
-                }
-                Direction::South => {
-                    let stop_y = WINDOW_HEIGHT as i32 / 2 - ROAD_WIDTH as i32 / 2;
-                    vehicle.position.y >= stop_y - VEHICLE_HEIGHT as i32
-                        && vehicle.position.y <= stop_y - VEHICLE_HEIGHT as i32 + 5
-                }
-                Direction::East => {
-                    let stop_x = (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - 5;
-                    vehicle.position.x >= stop_x - VEHICLE_WIDTH as i32
-                        && vehicle.position.x <= stop_x - VEHICLE_WIDTH as i32 + 5
-                }
-                Direction::West => {
-                    let stop_x = (WINDOW_WIDTH as i32 / 2 + ROAD_WIDTH as i32 / 2) - 5;
-                    vehicle.position.x >= stop_x && vehicle.position.x <= stop_x + 5
-                }
+            let at_stop_line = vehicle.at_stop_line(&g);
+
+            if at_stop_line && !vehicle.has_passed_intersection && !vehicle.arrived_at_stop_line {
+                vehicle.arrived_at_stop_line = true;
+                events.push((
+                    vehicle.id,
+                    VehicleEventKind::ArrivedAtStopLine {
+                        direction: vehicle.direction,
+                    },
+                ));
+            }
+
+            let should_stop_at_light = if !vehicle.has_passed_intersection && !may_enter {
+                at_stop_line
+            } else {
+                false
             };
 
-            let should_stop_at_light =
-                if !vehicle.has_passed_intersection && light_state != TrafficLightState::Green {
-                    at_stop_line
-                } else {
-                    false
-                };
+            // A reservation or the yield at the roundabout already keeps the path clear
+            let should_wait_for_intersection_clear = if at_stop_line
+                && may_enter
+                && !matches!(control, ControlMode::Reservation | ControlMode::Roundabout)
+            {
+                box_users
+                    .iter()
+                    .any(|&(dir, turned_back)| dir != vehicle.direction || turned_back)
+            } else {
+                false
+            };
 
-            let should_wait_for_intersection_clear =
-                if at_stop_line && light_state == TrafficLightState::Green {
-                    vehicle_positions
-                        .iter()
-                        .enumerate()
-                        .any(|(j, (pos, dir, _))| {
-                            j != i && *dir != vehicle.direction && is_in_intersection(*pos)
-                        })
-                } else {
-                    false
+            // A U-turn comes back down the opposing exit lane, so it waits in
+            // the box until its spot on that lane is clear
+            let should_wait_to_turn_back = vehicle.route == Route::UTurn
+                && !vehicle.has_turned
+                && control != ControlMode::Reservation
+                && {
+                    let mut turned = vehicle.clone();
+                    let speed = turned.step(limits.free_flow(&turned, &g));
+                    turned.advance(&g, speed, turn_offset);
+                    let (x0, x1, y0, y1) = sprite_area(turned.position, turned.direction);
+                    turned.has_turned
+                        && vehicle_positions.iter().enumerate().any(
+                            |(j, &(other_pos, other_dir, _))| {
+                                let (ox0, ox1, oy0, oy1) = sprite_area(other_pos, other_dir);
+                                j != i
+                                    && ((x0 < ox1 && ox0 < x1 && y0 < oy1 && oy0 < y1)
+                                        || turned.too_close_behind(
+                                            other_pos,
+                                            other_dir,
+                                            min_distance,
+                                        ))
+                            },
+                        )
                 };
 
             if !should_stop_at_light
                 && !should_stop_for_vehicle
                 && !should_wait_for_intersection_clear
+                && !should_wait_to_turn_back
             {
-                let intersection_center_x = WINDOW_WIDTH as i32 / 2;
-                let intersection_center_y = WINDOW_HEIGHT as i32 / 2;
-                if !vehicle.has_passed_intersection {
-                    match vehicle.direction {
-                        Direction::North => {
-                            if vehicle.route == Route::Right
-                                && vehicle.position.y <= intersection_center_y - TURN_OFFSET
-                            {
-                                vehicle.has_passed_intersection = true;
-                            } else if (vehicle.route == Route::Left
-                                || vehicle.route == Route::Straight)
-                                && vehicle.position.y <= intersection_center_y
-                            {
-                                vehicle.has_passed_intersection = true;
-                            }
-                        }
-                        Direction::South => {
-                            if vehicle.position.y >= intersection_center_y - TURN_OFFSET {
-                                vehicle.has_passed_intersection = true;
-                            }
-                        }
-                        Direction::East => {
-                            if vehicle.route == Route::Right
-                                && vehicle.position.x >= intersection_center_x
-                            {
-                                vehicle.has_passed_intersection = true;
-                            } else if (vehicle.route == Route::Left
-                                || vehicle.route == Route::Straight)
-                                && vehicle.position.x >= intersection_center_x - TURN_OFFSET
-                            {
-                                vehicle.has_passed_intersection = true;
-                            }
-                        }
-                        Direction::West => {
-                            if vehicle.route == Route::Left
-                                && vehicle.position.x <= intersection_center_x
-                            {
-                                vehicle.has_passed_intersection = true;
-                            } else if (vehicle.route == Route::Right
-                                || vehicle.route == Route::Straight)
-                                && vehicle.position.x <= intersection_center_x - TURN_OFFSET
-                            {
-                                vehicle.has_passed_intersection = true;
-                            }
-                        }
-                    }
+                // Moving off the line claims the box for the rest of the tick
+                if at_stop_line && !vehicle.has_passed_intersection {
+                    box_users.push((vehicle.direction, false));
                 }
-
-                if vehicle.has_passed_intersection && !vehicle.has_turned {
-                    match (vehicle.direction, vehicle.route) {
-                        (Direction::North, Route::Left) => {
-                            if vehicle.position.y <= intersection_center_y {
-                                vehicle.direction = Direction::West;
-                                vehicle.position = Point::new(
-                                    intersection_center_x
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::North, Route::Right) => {
-                            if vehicle.position.y <= intersection_center_y {
-                                vehicle.direction = Direction::East;
-                                vehicle.position = Point::new(
-                                    intersection_center_x
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::South, Route::Left) => {
-                            let stop_y = (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - 5;
-                            if vehicle.position.y + VEHICLE_WIDTH as i32 >= stop_y - 5 {
-                                vehicle.direction = Direction::East;
-                                vehicle.position = Point::new(
-                                    intersection_center_x + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::South, Route::Right) => {
-                            if vehicle.position.y >= intersection_center_y {
-                                vehicle.direction = Direction::West;
-                                vehicle.position = Point::new(
-                                    intersection_center_x + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::East, Route::Left) => {
-                            let stop_x = (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - 5;
-                            if vehicle.position.x + VEHICLE_WIDTH as i32 >= stop_x - 5 {
-                                vehicle.direction = Direction::North;
-                                vehicle.position = Point::new(
-                                    intersection_center_x
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::East, Route::Right) => {
-                            let stop_x = (WINDOW_WIDTH as i32 / 2 - ROAD_WIDTH as i32 / 2) - 5;
-                            if vehicle.position.x + VEHICLE_WIDTH as i32 >= stop_x - 5 {
-                                vehicle.direction = Direction::South;
-                                vehicle.position = Point::new(
-                                    intersection_center_x + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::West, Route::Left) => {
-                            if vehicle.position.x <= intersection_center_x {
-                                vehicle.direction = Direction::South;
-                                vehicle.position = Point::new(
-                                    intersection_center_x + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        (Direction::West, Route::Right) => {
-                            if vehicle.position.x <= intersection_center_x {
-                                vehicle.direction = Direction::North;
-                                vehicle.position = Point::new(
-                                    intersection_center_x
-                                        - ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_WIDTH as i32 / 2,
-                                    intersection_center_y + ROAD_WIDTH as i32 / 4
-                                        - VEHICLE_HEIGHT as i32 / 2,
-                                );
-                                vehicle.has_turned = true;
-                            }
-                        }
-                        _ => {}
+                let direction_before_turn = vehicle.direction;
+                let speed = vehicle.step(limits.free_flow(vehicle, &g));
+                if control == ControlMode::Roundabout {
+                    vehicle.drive(speed);
+                    if !vehicle.has_passed_intersection && roundabout.reached(vehicle) {
+                        roundabout.join(vehicle);
                     }
+                } else {
+                    vehicle.advance(&g, speed, turn_offset);
+                }
+                if vehicle.direction != direction_before_turn {
+                    events.push((
+                        vehicle.id,
+                        VehicleEventKind::Turned {
+                            from: direction_before_turn,
+                            to: vehicle.direction,
+                        },
+                    ));
                 }
 
-                match vehicle.direction {
-                    Direction::North => vehicle.position.y -= VEHICLE_SPEED,
-                    Direction::South => vehicle.position.y += VEHICLE_SPEED,
-                    Direction::East => vehicle.position.x += VEHICLE_SPEED,
-                    Direction::West => vehicle.position.x -= VEHICLE_SPEED,
+                let entered = match control {
+                    ControlMode::Roundabout => vehicle.circulated.is_some(),
+                    _ => is_in_intersection(vehicle.position),
+                };
+                if !vehicle.entered_intersection && entered {
+                    vehicle.entered_intersection = true;
+                    events.push((
+                        vehicle.id,
+                        VehicleEventKind::EnteredIntersection {
+                            direction: vehicle.direction,
+                        },
+                    ));
                 }
+            } else {
+                vehicle.wait_ticks += 1;
             }
         }
 
-        for i in to_remove.into_iter().rev() {
-            self.vehicles.remove(i);
-        }
-    }
-
-    fn render(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
-        canvas.set_draw_color(Color::RGB(50, 50, 50));
-        canvas.clear();
-
-        // Draw roads
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.fill_rect(Rect::new(
-            0,
-            (WINDOW_HEIGHT as i32 / 2) - (ROAD_WIDTH as i32 / 2),
-            WINDOW_WIDTH,
-            ROAD_WIDTH,
-        ))?;
-        canvas.fill_rect(Rect::new(
-            (WINDOW_WIDTH as i32 / 2) - (ROAD_WIDTH as i32 / 2),
-            0,
-            ROAD_WIDTH,
-            WINDOW_HEIGHT,
-        ))?;
-
-        // Draw road markings
-        canvas.set_draw_color(Color::RGB(255, 255, 255));
-        let horizontal_center = WINDOW_HEIGHT as i32 / 2;
-        for x in (0..WINDOW_WIDTH as i32).step_by(30) {
-            canvas.fill_rect(Rect::new(x, horizontal_center, 15, 2))?;
-        }
-        let vertical_center = WINDOW_WIDTH as i32 / 2;
-        for y in (0..WINDOW_HEIGHT as i32).step_by(30) {
-            canvas.fill_rect(Rect::new(vertical_center, y, 2, 15))?;
-        }
-
-        // Render traffic lights
-        for light in &self.traffic_lights {
-            let texture = self
-                .traffic_light_textures
-                .get(&light.state)
-                .ok_or("Failed to get traffic light texture")?;
-            canvas.copy(
-                texture,
-                None,
-                Rect::new(
-                    light.position.x,
-                    light.position.y,
-                    TRAFFIC_LIGHT_SIZE,
-                    TRAFFIC_LIGHT_SIZE,
-                ),
-            )?;
+        self.vehicles
+            .retain(|vehicle| !exited.contains(&vehicle.id));
+        for (vehicle, kind) in events {
+            self.emit(vehicle, kind);
         }
+    }
+}
 
-        // Render vehicles
-        for vehicle in &self.vehicles {
-            let texture = self
-                .vehicle_textures
-                .get(&(vehicle.direction, vehicle.route))
-                .ok_or("Failed to get vehicle texture")?;
+// Screen area a vehicle covers, as left, right, top and bottom edges
+fn sprite_area(position: Point, direction: Direction) -> (i32, i32, i32, i32) {
+    let (width, height) = match direction {
+        Direction::North | Direction::South => (VEHICLE_WIDTH as i32, VEHICLE_HEIGHT as i32),
+        Direction::East | Direction::West => (VEHICLE_HEIGHT as i32, VEHICLE_WIDTH as i32),
+    };
+    (
+        position.x + SPRITE_INSET,
+        position.x + width - SPRITE_INSET,
+        position.y + SPRITE_INSET,
+        position.y + height - SPRITE_INSET,
+    )
+}
 
-            let (width, height) = match vehicle.direction {
-                Direction::North | Direction::South => (VEHICLE_WIDTH, VEHICLE_HEIGHT),
-                Direction::East | Direction::West => (VEHICLE_HEIGHT, VEHICLE_WIDTH),
-            };
-            canvas.copy(
-                texture,
-                None,
-                Rect::new(vehicle.position.x, vehicle.position.y, width, height),
-            )?;
+// Uses --config if given, otherwise config.toml when present, otherwise defaults
+fn load_config(path: Option<&Path>) -> Result<Config, String> {
+    match path {
+        Some(path) => Config::load(path),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+            Config::load(Path::new(DEFAULT_CONFIG_PATH))
         }
+        None => Ok(Config::default()),
+    }
+}
 
-        canvas.present();
-        Ok(())
+// Works out the config a run uses and the snapshot it starts from, if any.
+// A snapshot carries its own config; command-line overrides apply to both.
+fn resolve_config(sim: &SimArgs) -> Result<(Config, Option<TrafficSystem>), String> {
+    let mut start_from = match &sim.start_from {
+        Some(path) => Some(snapshot::load(path)?),
+        None => None,
+    };
+    let mut config = match &start_from {
+        Some(snapshot) => snapshot.config.clone(),
+        None => load_config(sim.config.as_deref())?,
+    };
+
+    if let Some(control) = sim.control {
+        config.intersection.control = control;
+        if let Some(snapshot) = start_from.as_mut() {
+            snapshot.config.intersection.control = control;
+        }
     }
+    if let Some(controller) = sim.controller {
+        config.signals.controller = controller;
+        if let Some(snapshot) = start_from.as_mut() {
+            snapshot.config.signals.controller = controller;
+        }
+    }
+    // Some checks depend on the control mode, e.g. the roundabout radius
+    config
+        .validate()
+        .map_err(|e| format!("Invalid config with the command-line overrides: {}", e))?;
+
+    Ok((config, start_from))
 }
 
-fn main() -> Result<(), String> {
+// SDL handles kept alive for the length of a windowed run
+struct Display {
+    _sdl_context: sdl2::Sdl,
+    _image_context: sdl2::image::Sdl2ImageContext,
+    canvas: Canvas<Window>,
+    event_pump: sdl2::EventPump,
+}
+
+fn open_display(config: &Config) -> Result<Display, String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
-    let _image_context = sdl2::image::init(InitFlag::PNG)?;
+    let image_context = sdl2::image::init(InitFlag::PNG)?;
 
     let window = video_subsystem
         .window(
             "Traffic Intersection Simulation",
-            WINDOW_WIDTH,
-            WINDOW_HEIGHT,
+            config.window.width,
+            config.window.height,
         )
         .position_centered()
         .build()
         .map_err(|e| e.to_string())?;
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-    let texture_creator = canvas.texture_creator();
-    let mut event_pump = sdl_context.event_pump()?;
+    let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let event_pump = sdl_context.event_pump()?;
+    Ok(Display {
+        _sdl_context: sdl_context,
+        _image_context: image_context,
+        canvas,
+        event_pump,
+    })
+}
 
-    let mut traffic_system = TrafficSystem::new(&texture_creator)?;
+fn frame_delay() {
+    ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / TICKS_PER_SECOND));
+}
+
+// Maps simulation hotkeys to inputs
+fn input_for_key(keycode: Keycode) -> Option<Input> {
+    match keycode {
+        Keycode::Up => Some(Input::Spawn(Direction::North)),
+        Keycode::Down => Some(Input::Spawn(Direction::South)),
+        Keycode::Left => Some(Input::Spawn(Direction::West)),
+        Keycode::Right => Some(Input::Spawn(Direction::East)),
+        Keycode::R => Some(Input::SpawnRandom),
+        _ => None,
+    }
+}
+
+// F cycles flashing yellow, flashing red, normal operation and back to the schedule
+fn next_flash_override(current: Option<FlashMode>) -> Option<FlashMode> {
+    match current {
+        None => Some(FlashMode::Yellow),
+        Some(FlashMode::Yellow) => Some(FlashMode::Red),
+        Some(FlashMode::Red) => Some(FlashMode::Off),
+        Some(FlashMode::Off) => None,
+    }
+}
+
+fn play(args: PlayArgs) -> Result<(), String> {
+    let (config, start_from) = resolve_config(&args.sim)?;
+    let mut display = open_display(&config)?;
+    let texture_creator = display.canvas.texture_creator();
+    let renderer = Renderer::new(&texture_creator)?;
+
+    let mut session = Session::start(&args.sim, &args.output, config, start_from)?;
     let mut paused = false;
 
     'running: loop {
-        for event in event_pump.poll_iter() {
+        for event in display.event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => break 'running,
                 Event::KeyDown {
@@ -808,12 +1495,161 @@ fn main() -> Result<(), String> {
                     ..
                 } => match keycode {
                     Keycode::Escape => break 'running,
-                    Keycode::Up => traffic_system.spawn_vehicle(Direction::North),
-                    Keycode::Down => traffic_system.spawn_vehicle(Direction::South),
-                    Keycode::Left => traffic_system.spawn_vehicle(Direction::West),
-                    Keycode::Right => traffic_system.spawn_vehicle(Direction::East),
-                    Keycode::R => traffic_system.spawn_random_vehicle(),
                     Keycode::P => paused = !paused,
+                    Keycode::F5 => match snapshot::save(&session.system, &args.snapshot) {
+                        Ok(()) => println!("Saved snapshot to {}", args.snapshot.display()),
+                        Err(e) => eprintln!("{}", e),
+                    },
+                    Keycode::F => {
+                        let next = next_flash_override(session.system.flash_override);
+                        session.apply(Input::SetFlash(next));
+                        match next {
+                            Some(mode) => println!("Flashing mode: {:?}", mode),
+                            None => println!("Flashing mode: following the schedule"),
+                        }
+                    }
+                    Keycode::F9 => match snapshot::load(&args.snapshot) {
+                        Ok(snapshot) => {
+                            session.apply(Input::Restore(Box::new(snapshot)));
+                            println!("Restored snapshot from {}", args.snapshot.display());
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    },
+                    _ => {
+                        if let Some(input) = input_for_key(keycode) {
+                            session.apply(input);
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        if !paused {
+            session.step()?;
+        }
+        session.flush_events()?;
+
+        renderer.render(&mut display.canvas, &session.system)?;
+        frame_delay();
+    }
+
+    session.finish()?;
+    Ok(())
+}
+
+// Runs a fixed number of ticks, asking for a random vehicle once per spawn
+// cooldown as if R were held down (unless configured demand or a scenario
+// supplies vehicles).
+// Headless runs skip SDL entirely and don't sleep between ticks.
+fn run(args: RunArgs) -> Result<(), String> {
+    let (config, start_from) = resolve_config(&args.sim)?;
+    let mut display = if args.headless {
+        None
+    } else {
+        Some(open_display(&config)?)
+    };
+    let texture_creator = display
+        .as_ref()
+        .map(|display| display.canvas.texture_creator());
+    let renderer = match &texture_creator {
+        Some(texture_creator) => Some(Renderer::new(texture_creator)?),
+        None => None,
+    };
+
+    let mut session = Session::start(&args.sim, &args.output, config, start_from)?;
+    let ticks = args
+        .ticks
+        .or(session.scenario().and_then(|scenario| scenario.ticks))
+        .ok_or("--ticks is required unless the scenario sets ticks")?;
+    println!("Seed: {}", session.seed);
+    if let Some(scenario) = session.scenario().filter(|s| !s.description.is_empty()) {
+        println!("Scenario: {}", scenario.description);
+    }
+
+    // Cooldowns are per approach, so pace the requests to keep the old overall rate
+    let spawn_interval = session.system.config.spawn_cooldown_ticks().max(1);
+    for i in 0..ticks {
+        if !args.no_random_spawns && session.random_spawns() && i % spawn_interval == 0 {
+            session.apply(Input::SpawnRandom);
+        }
+        session.step()?;
+        session.flush_events()?;
+
+        if let (Some(display), Some(renderer)) = (display.as_mut(), renderer.as_ref()) {
+            let quit = display.event_pump.poll_iter().any(|event| {
+                matches!(
+                    event,
+                    Event::Quit { .. }
+                        | Event::KeyDown {
+                            keycode: Some(Keycode::Escape),
+                            ..
+                        }
+                )
+            });
+            if quit {
+                break;
+            }
+            renderer.render(&mut display.canvas, &session.system)?;
+            frame_delay();
+        }
+    }
+
+    let system = session.finish()?;
+    print_summary(&system);
+    Ok(())
+}
+
+// Plays a recording back. P/Space pauses, Period steps one tick, Left/Right
+// seek five seconds and Home restarts. Headless playback just checks that the
+// recording still reproduces.
+fn replay(args: ReplayArgs) -> Result<(), String> {
+    let replay = Replay::load(&args.file)?;
+    if args.headless {
+        let mut player = ReplayPlayer::new(replay);
+        player.seek(player.len());
+        print_summary(player.system());
+        return match player.diverged_at() {
+            Some(frame) => Err(format!(
+                "{} diverged from the recording at frame {}",
+                args.file.display(),
+                frame
+            )),
+            None => {
+                println!(
+                    "{} reproduced all {} frames",
+                    args.file.display(),
+                    player.len()
+                );
+                Ok(())
+            }
+        };
+    }
+
+    let mut display = open_display(replay.config())?;
+    let texture_creator = display.canvas.texture_creator();
+    let renderer = Renderer::new(&texture_creator)?;
+    let mut player = ReplayPlayer::new(replay);
+    let seek_frames = 5 * TICKS_PER_SECOND as usize;
+    let mut paused = false;
+
+    'running: loop {
+        for event in display.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => match keycode {
+                    Keycode::Escape => break 'running,
+                    Keycode::P | Keycode::Space => paused = !paused,
+                    Keycode::Period => {
+                        paused = true;
+                        player.step();
+                    }
+                    Keycode::Left => player.seek(player.position().saturating_sub(seek_frames)),
+                    Keycode::Right => player.seek(player.position() + seek_frames),
+                    Keycode::Home => player.seek(0),
                     _ => {}
                 },
                 _ => {}
@@ -821,13 +1657,374 @@ fn main() -> Result<(), String> {
         }
 
         if !paused {
-            traffic_system.update_traffic_lights();
-            traffic_system.update_vehicles();
+            player.step();
         }
 
-        traffic_system.render(&mut canvas)?;
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        let mut status = String::from(if player.is_finished() {
+            "finished"
+        } else if paused {
+            "paused"
+        } else {
+            "playing"
+        });
+        if let Some(frame) = player.diverged_at() {
+            status.push_str(&format!(", diverged at frame {}", frame));
+        }
+        display
+            .canvas
+            .window_mut()
+            .set_title(&format!(
+                "Replay - frame {}/{} ({})",
+                player.position(),
+                player.len(),
+                status
+            ))
+            .map_err(|e| e.to_string())?;
+
+        renderer.render(&mut display.canvas, player.system())?;
+        frame_delay();
     }
 
     Ok(())
 }
+
+// Serves the RL environment protocol on stdin/stdout until close or end of input
+fn env(args: EnvArgs) -> Result<(), String> {
+    let env = env::Env::new(args)?;
+    env::serve(env, std::io::stdin().lock(), std::io::stdout().lock())
+}
+
+fn main() -> Result<(), String> {
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Play(cli.play)) {
+        Command::Play(args) => play(args),
+        Command::Run(args) => run(args),
+        Command::Replay(args) => replay(args),
+        Command::Env(args) => env(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ticks_from_ms;
+
+    // The vehicles on the road after each of the next `ticks` updates
+    fn history(system: &mut TrafficSystem, ticks: u64) -> Vec<Vec<Vehicle>> {
+        (0..ticks)
+            .map(|_| {
+                system.update();
+                system.vehicles.clone()
+            })
+            .collect()
+    }
+
+    fn spawn(system: &mut TrafficSystem, direction: Direction) -> VehicleId {
+        system.spawn_vehicle_on_route(direction, Route::Straight);
+        system.vehicles.last().unwrap().id
+    }
+
+    // First tick the vehicle is across its stop line, and how long it had
+    // stood still by then
+    fn crossing(history: &[Vec<Vehicle>], id: VehicleId, g: &Geometry) -> (usize, u64) {
+        history
+            .iter()
+            .enumerate()
+            .find_map(|(tick, vehicles)| {
+                vehicles
+                    .iter()
+                    .find(|v| {
+                        v.id == id
+                            && (v.has_passed_intersection
+                                || (!v.at_stop_line(g) && v.distance_to_stop_line(g) < 0))
+                    })
+                    .map(|v| (tick, v.wait_ticks))
+            })
+            .expect("vehicle never crossed its stop line")
+    }
+
+    fn light_state(system: &TrafficSystem, direction: Direction) -> TrafficLightState {
+        system
+            .traffic_lights
+            .iter()
+            .find(|light| light.direction == direction)
+            .map(|light| light.state)
+            .unwrap()
+    }
+
+    #[test]
+    fn forced_red_approach_never_starves_the_others() {
+        let mut system = TrafficSystem::new(Config::default(), 1);
+        system.apply(Input::ForceLight(
+            Direction::North,
+            Some(TrafficLightState::Red),
+        ));
+        // The held approach has the longest queue by far
+        for _ in 0..6 {
+            system.spawn_vehicle_on_route(Direction::North, Route::Straight);
+        }
+        system.spawn_vehicle_on_route(Direction::East, Route::Straight);
+
+        // After the first decision, whichever other approach has vehicles
+        // waiting always has a green to run on
+        for _ in 0..1200 {
+            system.update();
+            assert_eq!(
+                light_state(&system, Direction::North),
+                TrafficLightState::Red
+            );
+            let others_waiting = system
+                .queue_counts()
+                .iter()
+                .any(|&(direction, count)| direction != Direction::North && count > 0);
+            let others_green = [Direction::South, Direction::East, Direction::West]
+                .iter()
+                .any(|&direction| light_state(&system, direction) == TrafficLightState::Green);
+            assert!(
+                !others_waiting || others_green,
+                "nothing green at tick {}",
+                system.tick
+            );
+        }
+        assert!(
+            system
+                .vehicles
+                .iter()
+                .all(|v| v.direction != Direction::East),
+            "the east vehicle is still waiting"
+        );
+    }
+
+    // U-turns cross the opposing approach and land on its exit lane; under
+    // signals they have to wait for both instead of driving over them
+    #[test]
+    fn u_turns_never_overlap_in_the_box() {
+        let config: Config = toml::from_str(
+            r#"
+            [road]
+            drive_on = "right"
+
+            [demand.northbound]
+            vehicles_per_hour = 600
+            u_turn = 1
+            [demand.southbound]
+            vehicles_per_hour = 600
+            u_turn = 1
+            [demand.eastbound]
+            vehicles_per_hour = 600
+            u_turn = 1
+            [demand.westbound]
+            vehicles_per_hour = 600
+            u_turn = 1
+            "#,
+        )
+        .expect("test config should parse");
+        let g = config.geometry();
+        let mut system = TrafficSystem::new(config, 2);
+        for _ in 0..12000 {
+            system.update();
+            let areas: Vec<_> = system
+                .vehicles
+                .iter()
+                .filter_map(|v| reservation::area_covered(v, &g).map(|area| (v.id, area)))
+                .collect();
+            for (i, &(a, (ax0, ax1, ay0, ay1))) in areas.iter().enumerate() {
+                for &(b, (bx0, bx1, by0, by1)) in &areas[i + 1..] {
+                    assert!(
+                        !(ax0 < bx1 && bx0 < ax1 && ay0 < by1 && by0 < ay1),
+                        "vehicles {:?} and {:?} overlap at tick {}",
+                        a,
+                        b,
+                        system.tick
+                    );
+                }
+            }
+        }
+    }
+
+    // Driving on the right is driving on the left in a mirror: spawn points,
+    // stop lines and turn points all have to line up with the reflection
+    #[test]
+    fn right_hand_layout_mirrors_the_left_hand_one() {
+        let left_config = Config::default();
+        let mut right_config = Config::default();
+        right_config.road.drive_on = DriveOn::Right;
+        let g = right_config.geometry();
+        let directions = [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ];
+        let routes = [Route::Straight, Route::Left, Route::Right, Route::UTurn];
+        for direction in directions {
+            for route in routes {
+                let mut left = TrafficSystem::new(left_config.clone(), 1);
+                let mut right = TrafficSystem::new(right_config.clone(), 1);
+                // Held at red long enough to stop at the line
+                left.apply(Input::ForceLight(direction, Some(TrafficLightState::Red)));
+                right.apply(Input::ForceLight(
+                    direction.mirrored(),
+                    Some(TrafficLightState::Red),
+                ));
+                left.spawn_vehicle_on_route(direction, route);
+                right.spawn_vehicle_on_route(direction.mirrored(), route.mirrored());
+                for tick in 0..1200 {
+                    if tick == 400 {
+                        left.apply(Input::ForceLight(direction, None));
+                        right.apply(Input::ForceLight(direction.mirrored(), None));
+                    }
+                    left.update();
+                    right.update();
+                    // Leaving is judged on the sprite's corner 100 pixels off
+                    // screen, so the two may drop out a few ticks apart
+                    let (Some(l), Some(r)) = (left.vehicles.first(), right.vehicles.first()) else {
+                        continue;
+                    };
+                    let mut mirrored = l.clone();
+                    mirrored.mirror(&g);
+                    assert_eq!(
+                        (mirrored.position, mirrored.direction, mirrored.route),
+                        (r.position, r.direction, r.route),
+                        "{:?} {:?} at tick {}",
+                        direction,
+                        route,
+                        tick
+                    );
+                    assert_eq!(l.has_turned, r.has_turned);
+                }
+                assert!(
+                    left.vehicles.is_empty() && right.vehicles.is_empty(),
+                    "{:?} {:?} never left",
+                    direction,
+                    route
+                );
+                assert!(left.metrics.total_wait_ticks > 0);
+            }
+        }
+    }
+
+    // Flashing red everywhere is an all-way stop: a full stop at the line,
+    // then one vehicle at a time in the order they got there
+    #[test]
+    fn flashing_red_acts_as_a_stop() {
+        let config = Config::default();
+        let g = config.geometry();
+        let stop_ticks = ticks_from_ms(config.intersection.stop_ms);
+        let mut system = TrafficSystem::new(config, 1);
+        system.apply(Input::SetFlash(Some(FlashMode::Red)));
+        let north = spawn(&mut system, Direction::North);
+        let east = spawn(&mut system, Direction::East);
+        let history = history(&mut system, 1200);
+
+        for direction in [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ] {
+            assert_eq!(
+                light_state(&system, direction),
+                TrafficLightState::FlashingRed
+            );
+        }
+        let (north_in, north_wait) = crossing(&history, north, &g);
+        let (east_in, east_wait) = crossing(&history, east, &g);
+        assert!(north_wait >= stop_ticks, "north stood {} ticks", north_wait);
+        assert!(east_wait >= stop_ticks, "east stood {} ticks", east_wait);
+        // North is nearer its line, so it goes first and east waits for it
+        assert!(north_in < east_in);
+        assert!(
+            history[east_in]
+                .iter()
+                .all(|v| v.id != north || v.has_passed_intersection),
+            "east went in while north was still crossing"
+        );
+        assert!(system.vehicles.is_empty());
+    }
+
+    // Flashing yellow on the major road lets it drive on, and the flashing
+    // red minor road gives way to it
+    #[test]
+    fn flashing_yellow_acts_as_a_yield() {
+        let config = Config::default();
+        let g = config.geometry();
+        let major_road = config.intersection.major_road;
+        let mut system = TrafficSystem::new(config, 1);
+        system.apply(Input::SetFlash(Some(FlashMode::Yellow)));
+        let (major, minor) = if major_road.carries(Direction::North) {
+            (Direction::North, Direction::East)
+        } else {
+            (Direction::East, Direction::North)
+        };
+        // The minor vehicle is at its line first, but the major one is
+        // within the gap by the time it has stopped
+        let minor_id = spawn(&mut system, minor);
+        let mut history = history(&mut system, 100);
+        let major_id = spawn(&mut system, major);
+        history.extend(self::history(&mut system, 1100));
+
+        assert_eq!(
+            light_state(&system, major),
+            TrafficLightState::FlashingYellow
+        );
+        assert_eq!(light_state(&system, minor), TrafficLightState::FlashingRed);
+        // The major road never stops
+        assert!(
+            history
+                .iter()
+                .flatten()
+                .all(|v| v.id != major_id || v.wait_ticks == 0)
+        );
+        let (major_in, _) = crossing(&history, major_id, &g);
+        let (minor_in, minor_wait) = crossing(&history, minor_id, &g);
+        assert!(minor_wait > ticks_from_ms(system.config.intersection.stop_ms));
+        assert!(major_in < minor_in);
+        assert!(system.vehicles.is_empty());
+    }
+
+    // A dark signal is a stop sign; with every light dark the intersection
+    // runs as an all-way stop
+    #[test]
+    fn dark_signals_fall_back_to_an_all_way_stop() {
+        let config = Config::default();
+        let g = config.geometry();
+        let stop_ticks = ticks_from_ms(config.intersection.stop_ms);
+        let mut all_way_stop = config.clone();
+        all_way_stop.intersection.control = ControlMode::AllWayStop;
+
+        let mut dark = TrafficSystem::new(config, 1);
+        for direction in [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ] {
+            dark.apply(Input::ForceLight(direction, Some(TrafficLightState::Dark)));
+        }
+        let mut stop_signs = TrafficSystem::new(all_way_stop, 1);
+        let mut crossings = Vec::new();
+        for system in [&mut dark, &mut stop_signs] {
+            let north = spawn(system, Direction::North);
+            let east = spawn(system, Direction::East);
+            let history = history(system, 1200);
+            let north = crossing(&history, north, &g);
+            let east = crossing(&history, east, &g);
+            assert!(north.1 >= stop_ticks && east.1 >= stop_ticks);
+            assert!(system.vehicles.is_empty());
+            crossings.push((north, east));
+        }
+        assert_eq!(crossings[0], crossings[1]);
+
+        // One dark light among working ones still makes its approach stop
+        let mut system = TrafficSystem::new(Config::default(), 1);
+        system.apply(Input::ForceLight(
+            Direction::North,
+            Some(TrafficLightState::Dark),
+        ));
+        let north = spawn(&mut system, Direction::North);
+        let history = history(&mut system, 1200);
+        assert!(crossing(&history, north, &g).1 >= stop_ticks);
+        assert!(system.vehicles.is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::config::{Geometry, IntersectionConfig, MaxPressureConfig, ticks_from_ms};
+use crate::{Direction, Route, Vehicle};
+use serde::{Deserialize, Serialize};
+
+// Phases in the order ties are broken
+const PHASES: [Direction; 4] = [
+    Direction::North,
+    Direction::East,
+    Direction::South,
+    Direction::West,
+];
+
+// Max-pressure control: at every decision point, give the green to the
+// approach whose movements have the largest total pressure, where a
+// movement's pressure is its queue minus the vehicles already on the road it
+// leads to. The chosen phase is held until the next decision point.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MaxPressureController {
+    green: Option<Direction>,
+    next_decision: u64,
+}
+
+impl MaxPressureController {
+    // Returns the approach that should have the green this tick
+    pub fn update(
+        &mut self,
+        vehicles: &[Vehicle],
+        g: &Geometry,
+        config: &MaxPressureConfig,
+        intersection: &IntersectionConfig,
+        tick: u64,
+    ) -> Option<Direction> {
+        if tick < self.next_decision {
+            return self.green;
+        }
+        self.next_decision = tick + ticks_from_ms(config.interval_ms).max(1);
+
+        let pressures = PHASES.map(|phase| (phase, pressure(vehicles, g, intersection, phase)));
+        let current = pressures
+            .iter()
+            .find(|(phase, _)| Some(*phase) == self.green)
+            .map(|&(_, pressure)| pressure);
+        // Ties keep the current phase, otherwise go to the first in PHASES
+        let mut best: Option<(Direction, i32)> = None;
+        for &(phase, pressure) in &pressures {
+            if best.is_none_or(|(_, best_pressure)| pressure > best_pressure) {
+                best = Some((phase, pressure));
+            }
+        }
+        if let Some((phase, pressure)) = best
+            && current.is_none_or(|current| pressure > current)
+            && vehicles.iter().any(|v| v.is_queued(g))
+        {
+            self.green = Some(phase);
+        }
+        self.green
+    }
+}
+
+// Sum over the phase's movements of queued vehicles minus exit-road occupancy.
+// U-turns are a movement of the phase only where they're allowed and someone
+// is queued to make one, so the opposing exit doesn't weigh on every phase.
+fn pressure(
+    vehicles: &[Vehicle],
+    g: &Geometry,
+    intersection: &IntersectionConfig,
+    phase: Direction,
+) -> i32 {
+    [Route::Straight, Route::Left, Route::Right, Route::UTurn]
+        .into_iter()
+        .filter(|&route| intersection.serves(phase, route))
+        .map(|route| {
+            let exit = phase.after(route);
+            let queued = vehicles
+                .iter()
+                .filter(|v| v.direction == phase && v.route == route && v.is_queued(g))
+                .count() as i32;
+            if route == Route::UTurn && queued == 0 {
+                return 0;
+            }
+            let downstream = vehicles
+                .iter()
+                .filter(|v| v.direction == exit && v.has_passed_intersection)
+                .count() as i32;
+            queued - downstream
+        })
+        .sum()
+}
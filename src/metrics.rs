@@ -0,0 +1,119 @@
+use crate::events::{VehicleEvent, VehicleEventKind};
+use crate::{Direction, VehicleId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Running totals collected from the vehicle event stream
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Metrics {
+    pub vehicles_spawned: u32,
+    pub vehicles_exited: u32,
+    pub total_travel_ticks: u64,
+    pub total_wait_ticks: u64,
+    pub max_wait_ticks: u64,
+    pub total_stop_line_delay_ticks: u64, // Arrival at the stop line until entering the box
+    pub max_stop_line_delay_ticks: u64,
+    pub vehicles_entered: u32,
+    pub exited_per_direction: HashMap<Direction, u32>, // Keyed by the direction the vehicle left in
+    pub total_entry_delay_ticks: u64, // Time spent queued off-screen before entering
+    pub vehicles_delayed_at_entry: u32,
+    pub max_entry_queue: u32,
+    pub phase_switches: u32, // Times a light turned green
+    spawn_ticks: HashMap<VehicleId, u64>,
+    arrival_ticks: HashMap<VehicleId, u64>,
+}
+
+impl Metrics {
+    pub fn record_event(&mut self, event: &VehicleEvent) {
+        match event.kind {
+            VehicleEventKind::Spawned { .. } => {
+                self.vehicles_spawned += 1;
+                self.spawn_ticks.insert(event.vehicle, event.tick);
+            }
+            VehicleEventKind::ArrivedAtStopLine { .. } => {
+                self.arrival_ticks.insert(event.vehicle, event.tick);
+            }
+            VehicleEventKind::EnteredIntersection { .. } => {
+                self.vehicles_entered += 1;
+                if let Some(arrival) = self.arrival_ticks.remove(&event.vehicle) {
+                    self.total_stop_line_delay_ticks += event.tick - arrival;
+                    self.max_stop_line_delay_ticks =
+                        self.max_stop_line_delay_ticks.max(event.tick - arrival);
+                }
+            }
+            VehicleEventKind::Turned { .. } => {}
+            VehicleEventKind::Exited {
+                direction,
+                wait_ticks,
+            } => {
+                self.vehicles_exited += 1;
+                if let Some(spawn) = self.spawn_ticks.remove(&event.vehicle) {
+                    self.total_travel_ticks += event.tick - spawn;
+                }
+                self.arrival_ticks.remove(&event.vehicle);
+                self.total_wait_ticks += wait_ticks;
+                self.max_wait_ticks = self.max_wait_ticks.max(wait_ticks);
+                *self.exited_per_direction.entry(direction).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn record_phase_switch(&mut self) {
+        self.phase_switches += 1;
+    }
+
+    pub fn record_entry_queue(&mut self, queued: u32) {
+        self.max_entry_queue = self.max_entry_queue.max(queued);
+    }
+
+    pub fn record_entry_delay(&mut self, delay_ticks: u64) {
+        self.total_entry_delay_ticks += delay_ticks;
+        if delay_ticks > 0 {
+            self.vehicles_delayed_at_entry += 1;
+        }
+    }
+
+    pub fn exited_towards(&self, direction: Direction) -> u32 {
+        self.exited_per_direction
+            .get(&direction)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn mean_travel_ticks(&self) -> f64 {
+        if self.vehicles_exited == 0 {
+            return 0.0;
+        }
+        self.total_travel_ticks as f64 / self.vehicles_exited as f64
+    }
+
+    pub fn mean_wait_ticks(&self) -> f64 {
+        if self.vehicles_exited == 0 {
+            return 0.0;
+        }
+        self.total_wait_ticks as f64 / self.vehicles_exited as f64
+    }
+
+    // Averaged over every vehicle that entered, including those that didn't queue
+    pub fn mean_entry_delay_ticks(&self) -> f64 {
+        if self.vehicles_spawned == 0 {
+            return 0.0;
+        }
+        self.total_entry_delay_ticks as f64 / self.vehicles_spawned as f64
+    }
+
+    pub fn mean_stop_line_delay_ticks(&self) -> f64 {
+        if self.vehicles_entered == 0 {
+            return 0.0;
+        }
+        self.total_stop_line_delay_ticks as f64 / self.vehicles_entered as f64
+    }
+
+    // Longest any vehicle has waited at the stop line, counting those still waiting
+    pub fn longest_stop_line_delay_ticks(&self, tick: u64) -> u64 {
+        self.arrival_ticks
+            .values()
+            .map(|&arrival| tick - arrival)
+            .fold(self.max_stop_line_delay_ticks, u64::max)
+    }
+}
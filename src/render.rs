@@ -0,0 +1,298 @@
+use crate::config::{ControlMode, Geometry};
+use crate::reservation::cell_rect;
+use crate::roundabout::Roundabout;
+use crate::speed::SpeedLimits;
+use crate::{
+    Direction, Route, TICKS_PER_SECOND, TRAFFIC_LIGHT_SIZE, TrafficLightState, TrafficSystem,
+    VEHICLE_HEIGHT, VEHICLE_WIDTH,
+};
+use sdl2::image::LoadTexture;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Owns the SDL textures and draws a TrafficSystem onto the canvas
+pub struct Renderer<'a> {
+    vehicle_textures: HashMap<(Direction, Route), Texture<'a>>,
+    traffic_light_textures: HashMap<TrafficLightState, Texture<'a>>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Result<Self, String> {
+        // Load vehicle textures for each Direction and Route combination
+        let mut vehicle_textures = HashMap::new();
+        let directions = [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ];
+        let routes = [Route::Straight, Route::Left, Route::Right, Route::UTurn];
+
+        for &direction in &directions {
+            for &route in &routes {
+                let texture_path = match (direction, route) {
+                    (Direction::North, Route::Straight) => "assets/vehicles/car_north_straight.png",
+                    (Direction::North, Route::Left) => "assets/vehicles/car_north_left.png",
+                    (Direction::North, Route::Right) => "assets/vehicles/car_north_right.png",
+                    (Direction::North, Route::UTurn) => "assets/vehicles/car_north_uturn.png",
+                    (Direction::South, Route::Straight) => "assets/vehicles/car_south_straight.png",
+                    (Direction::South, Route::Left) => "assets/vehicles/car_south_left.png",
+                    (Direction::South, Route::Right) => "assets/vehicles/car_south_right.png",
+                    (Direction::South, Route::UTurn) => "assets/vehicles/car_south_uturn.png",
+                    (Direction::East, Route::Straight) => "assets/vehicles/car_east_straight.png",
+                    (Direction::East, Route::Left) => "assets/vehicles/car_east_left.png",
+                    (Direction::East, Route::Right) => "assets/vehicles/car_east_right.png",
+                    (Direction::East, Route::UTurn) => "assets/vehicles/car_east_uturn.png",
+                    (Direction::West, Route::Straight) => "assets/vehicles/car_west_straight.png",
+                    (Direction::West, Route::Left) => "assets/vehicles/car_west_left.png",
+                    (Direction::West, Route::Right) => "assets/vehicles/car_west_right.png",
+                    (Direction::West, Route::UTurn) => "assets/vehicles/car_west_uturn.png",
+                };
+                vehicle_textures.insert(
+                    (direction, route),
+                    texture_creator
+                        .load_texture(Path::new(texture_path))
+                        .map_err(|e| format!("Failed to load {}: {}", texture_path, e))?,
+                );
+            }
+        }
+
+        // Load traffic light textures
+        let mut traffic_light_textures = HashMap::new();
+        traffic_light_textures.insert(
+            TrafficLightState::Red,
+            texture_creator
+                .load_texture(Path::new("assets/traffic_lights/red.png"))
+                .map_err(|e| e.to_string())?,
+        );
+        traffic_light_textures.insert(
+            TrafficLightState::Green,
+            texture_creator
+                .load_texture(Path::new("assets/traffic_lights/green.png"))
+                .map_err(|e| e.to_string())?,
+        );
+
+        Ok(Renderer {
+            vehicle_textures,
+            traffic_light_textures,
+        })
+    }
+
+    // Flashing lights are lit for the first half of every second
+    fn draw_light(
+        &self,
+        canvas: &mut Canvas<Window>,
+        state: TrafficLightState,
+        rect: Rect,
+        tick: u64,
+    ) -> Result<(), String> {
+        let second = TICKS_PER_SECOND as u64;
+        let lit = tick % second < second / 2;
+        let texture_state = match state {
+            TrafficLightState::Red | TrafficLightState::Green => Some(state),
+            TrafficLightState::FlashingRed if lit => Some(TrafficLightState::Red),
+            TrafficLightState::FlashingYellow if lit => {
+                canvas.set_draw_color(Color::RGB(255, 200, 0));
+                return canvas.fill_rect(rect);
+            }
+            _ => None,
+        };
+        match texture_state {
+            Some(state) => {
+                let texture = self
+                    .traffic_light_textures
+                    .get(&state)
+                    .ok_or("Failed to get traffic light texture")?;
+                canvas.copy(texture, None, rect)
+            }
+            // Unlit: an empty signal head
+            None => {
+                canvas.set_draw_color(Color::RGB(20, 20, 20));
+                canvas.fill_rect(rect)
+            }
+        }
+    }
+
+    pub fn render(
+        &self,
+        canvas: &mut Canvas<Window>,
+        system: &TrafficSystem,
+    ) -> Result<(), String> {
+        let g = system.config.geometry();
+        let road_width = system.config.road.width;
+
+        canvas.set_draw_color(Color::RGB(50, 50, 50));
+        canvas.clear();
+
+        // Draw roads
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.fill_rect(Rect::new(
+            0,
+            g.center_y - g.half_road,
+            g.width as u32,
+            road_width,
+        ))?;
+        canvas.fill_rect(Rect::new(
+            g.center_x - g.half_road,
+            0,
+            road_width,
+            g.height as u32,
+        ))?;
+
+        // Draw road markings
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        for x in (0..g.width).step_by(30) {
+            canvas.fill_rect(Rect::new(x, g.center_y, 15, 2))?;
+        }
+        for y in (0..g.height).step_by(30) {
+            canvas.fill_rect(Rect::new(g.center_x, y, 2, 15))?;
+        }
+
+        // The circulating road and its island cover the middle of the crossing
+        if system.config.intersection.control == ControlMode::Roundabout {
+            let radius = system.config.intersection.roundabout.radius;
+            let half_lane = g.half_road / 2;
+            fill_circle(canvas, &g, radius + half_lane, Color::RGB(0, 0, 0))?;
+            fill_circle(canvas, &g, radius - half_lane, Color::RGB(40, 110, 40))?;
+        }
+
+        // Render traffic lights, or the signs that replace them
+        let intersection = &system.config.intersection;
+        for light in &system.traffic_lights {
+            let rect = Rect::new(
+                light.position.x,
+                light.position.y,
+                TRAFFIC_LIGHT_SIZE,
+                TRAFFIC_LIGHT_SIZE,
+            );
+            match intersection.control {
+                ControlMode::Signals => self.draw_light(canvas, light.state, rect, system.tick)?,
+                ControlMode::AllWayStop => draw_stop_sign(canvas, rect)?,
+                ControlMode::Yield if !intersection.major_road.carries(light.direction) => {
+                    draw_yield_sign(canvas, rect)?
+                }
+                ControlMode::Roundabout => draw_yield_sign(canvas, rect)?,
+                ControlMode::Yield | ControlMode::Reservation => {}
+            }
+        }
+
+        // Outline the cells vehicles hold right now
+        if intersection.control == ControlMode::Reservation {
+            canvas.set_draw_color(Color::RGB(255, 200, 0));
+            for cell in system.reservations.reserved_cells(system.tick) {
+                canvas.draw_rect(cell_rect(&g, intersection.reservation.grid, cell))?;
+            }
+        }
+
+        // Render vehicles, turning those on the roundabout to follow the circle
+        let roundabout = Roundabout::new(
+            &g,
+            &intersection.roundabout,
+            SpeedLimits::new(&system.config).circulating(),
+            system.config.vehicles.min_distance,
+        );
+        for vehicle in &system.vehicles {
+            if let Some(heading) = roundabout.heading(vehicle) {
+                let texture = self
+                    .vehicle_textures
+                    .get(&(Direction::North, vehicle.route))
+                    .ok_or("Failed to get vehicle texture")?;
+                canvas.copy_ex(
+                    texture,
+                    None,
+                    Rect::new(
+                        vehicle.position.x,
+                        vehicle.position.y,
+                        VEHICLE_WIDTH,
+                        VEHICLE_HEIGHT,
+                    ),
+                    heading,
+                    None,
+                    false,
+                    false,
+                )?;
+                continue;
+            }
+
+            let texture = self
+                .vehicle_textures
+                .get(&(vehicle.direction, vehicle.route))
+                .ok_or("Failed to get vehicle texture")?;
+
+            let (width, height) = match vehicle.direction {
+                Direction::North | Direction::South => (VEHICLE_WIDTH, VEHICLE_HEIGHT),
+                Direction::East | Direction::West => (VEHICLE_HEIGHT, VEHICLE_WIDTH),
+            };
+            canvas.copy(
+                texture,
+                None,
+                Rect::new(vehicle.position.x, vehicle.position.y, width, height),
+            )?;
+        }
+
+        canvas.present();
+        Ok(())
+    }
+}
+
+// Red square with a white border
+fn draw_stop_sign(canvas: &mut Canvas<Window>, rect: Rect) -> Result<(), String> {
+    canvas.set_draw_color(Color::RGB(200, 0, 0));
+    canvas.fill_rect(rect)?;
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.draw_rect(Rect::new(
+        rect.x() + 2,
+        rect.y() + 2,
+        rect.width() - 4,
+        rect.height() - 4,
+    ))
+}
+
+// White triangle pointing down with a red border
+fn draw_yield_sign(canvas: &mut Canvas<Window>, rect: Rect) -> Result<(), String> {
+    fill_triangle(canvas, rect, Color::RGB(200, 0, 0))?;
+    let inner = Rect::new(
+        rect.x() + 4,
+        rect.y() + 2,
+        rect.width() - 8,
+        rect.height() - 8,
+    );
+    fill_triangle(canvas, inner, Color::RGB(255, 255, 255))
+}
+
+// Disc around the centre of the intersection, one row at a time
+fn fill_circle(
+    canvas: &mut Canvas<Window>,
+    g: &Geometry,
+    radius: i32,
+    color: Color,
+) -> Result<(), String> {
+    canvas.set_draw_color(color);
+    for dy in -radius..=radius {
+        let half = ((radius * radius - dy * dy) as f64).sqrt() as i32;
+        let y = g.center_y + dy;
+        canvas.draw_line(
+            Point::new(g.center_x - half, y),
+            Point::new(g.center_x + half, y),
+        )?;
+    }
+    Ok(())
+}
+
+// Triangle with its base along the top of the rectangle, one row at a time
+fn fill_triangle(canvas: &mut Canvas<Window>, rect: Rect, color: Color) -> Result<(), String> {
+    canvas.set_draw_color(color);
+    let width = rect.width() as i32;
+    let height = rect.height() as i32;
+    for row in 0..height {
+        let half = width * (height - row) / height / 2;
+        let center = rect.x() + width / 2;
+        let y = rect.y() + row;
+        canvas.draw_line(Point::new(center - half, y), Point::new(center + half, y))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use crate::config::Config;
+use crate::{Direction, Input, Route, TrafficLightState, TrafficSystem, VehicleId};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Bumped whenever the file layout or simulation rules change in a way that
+// would make older recordings play back differently
+const REPLAY_VERSION: u32 = 23;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct VehicleFrame {
+    id: VehicleId,
+    x: i32,
+    y: i32,
+    direction: Direction,
+    route: Route,
+}
+
+// Simulation state after a tick, used to detect playback divergence
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Frame {
+    tick: u64,
+    vehicles: Vec<VehicleFrame>,
+    lights: Vec<TrafficLightState>,
+}
+
+impl Frame {
+    fn capture(system: &TrafficSystem) -> Self {
+        Frame {
+            tick: system.tick,
+            vehicles: system
+                .vehicles
+                .iter()
+                .map(|vehicle| VehicleFrame {
+                    id: vehicle.id,
+                    x: vehicle.position.x,
+                    y: vehicle.position.y,
+                    direction: vehicle.direction,
+                    route: vehicle.route,
+                })
+                .collect(),
+            lights: system
+                .traffic_lights
+                .iter()
+                .map(|light| light.state)
+                .collect(),
+        }
+    }
+}
+
+// Everything needed to reproduce a run: the seed and config, every input with the number
+// of frames recorded before it was applied, and the state after each tick.
+// Inputs are keyed by frame rather than simulation tick because restoring a
+// snapshot moves the simulation clock.
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    version: u32,
+    seed: u64,
+    config: Config,
+    inputs: Vec<(usize, Input)>,
+    frames: Vec<Frame>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file =
+            File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let replay: Replay = bincode::DefaultOptions::new()
+            .deserialize_from(BufReader::new(file))
+            .map_err(|e| format!("Failed to read replay {}: {}", path.display(), e))?;
+        if replay.version != REPLAY_VERSION {
+            return Err(format!(
+                "Replay {} has version {}, expected {}",
+                path.display(),
+                replay.version,
+                REPLAY_VERSION
+            ));
+        }
+        Ok(replay)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let mut out = BufWriter::new(file);
+        // Variable-length integers keep per-tick frames small
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut out, self)
+            .map_err(|e| format!("Failed to write replay {}: {}", path.display(), e))?;
+        out.flush().map_err(|e| e.to_string())
+    }
+}
+
+// Collects inputs and frames during a live run and writes them out at the end
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf, seed: u64, config: Config) -> Self {
+        Recorder {
+            path,
+            replay: Replay {
+                version: REPLAY_VERSION,
+                seed,
+                config,
+                inputs: Vec::new(),
+                frames: Vec::new(),
+            },
+        }
+    }
+
+    pub fn record_input(&mut self, input: Input) {
+        self.replay.inputs.push((self.replay.frames.len(), input));
+    }
+
+    pub fn record_frame(&mut self, system: &TrafficSystem) {
+        self.replay.frames.push(Frame::capture(system));
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        self.replay.save(&self.path)
+    }
+}
+
+// Re-runs a recording tick by tick from its seed and inputs, checking each
+// tick against the recorded frame
+pub struct ReplayPlayer {
+    replay: Replay,
+    system: TrafficSystem,
+    position: usize, // Frames played so far
+    next_input: usize,
+    diverged_at: Option<usize>,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        let system = TrafficSystem::new(replay.config.clone(), replay.seed);
+        ReplayPlayer {
+            replay,
+            system,
+            position: 0,
+            next_input: 0,
+            diverged_at: None,
+        }
+    }
+
+    pub fn system(&self) -> &TrafficSystem {
+        &self.system
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.replay.frames.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.len()
+    }
+
+    pub fn diverged_at(&self) -> Option<usize> {
+        self.diverged_at
+    }
+
+    pub fn step(&mut self) {
+        if self.is_finished() {
+            return;
+        }
+
+        while let Some((position, input)) = self.replay.inputs.get(self.next_input) {
+            if *position > self.position {
+                break;
+            }
+            self.system.apply(input.clone());
+            self.next_input += 1;
+        }
+
+        self.system.update();
+        self.system.take_events();
+
+        let recorded = &self.replay.frames[self.position];
+        self.position += 1;
+        if self.diverged_at.is_none() && *recorded != Frame::capture(&self.system) {
+            self.diverged_at = Some(self.position);
+            eprintln!(
+                "Replay diverged from the recording at frame {} (tick {})",
+                self.position, self.system.tick
+            );
+        }
+    }
+
+    // Jumps to the given frame, replaying from the start when seeking backwards
+    pub fn seek(&mut self, target: usize) {
+        if target < self.position {
+            self.system = TrafficSystem::new(self.replay.config.clone(), self.replay.seed);
+            self.position = 0;
+            self.next_input = 0;
+        }
+        while self.position < target && !self.is_finished() {
+            self.step();
+        }
+    }
+}
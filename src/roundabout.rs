@@ -0,0 +1,189 @@
+use crate::config::{Geometry, RoundaboutConfig, ticks_from_ms};
+use crate::{Direction, VEHICLE_HEIGHT, VEHICLE_WIDTH, Vehicle};
+use sdl2::rect::Point;
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+// Single-lane roundabout in place of the crossing. A vehicle joins the circle
+// where its lane meets it and leaves at the exit its route leads to. Driving
+// on the left it circulates clockwise, with Left the first exit, Straight the
+// second, Right the third and a U-turn the fourth; driving on the right it
+// circulates anticlockwise, with Right first and Left third.
+// Angles are on screen, clockwise from east.
+pub struct Roundabout {
+    g: Geometry,
+    radius: f64,
+    turn: f64,    // 1 circulating clockwise, -1 anticlockwise
+    offset: f64,  // Angle between an arm's centre line and its lanes, on the circle
+    gap: i32,     // Gap an entering vehicle needs, as a distance round the circle
+    spacing: i32, // Closest two vehicles follow each other round, front to front
+}
+
+impl Roundabout {
+    pub fn new(g: &Geometry, config: &RoundaboutConfig, speed: f64, min_distance: i32) -> Self {
+        let radius = config.radius as f64;
+        Roundabout {
+            g: *g,
+            radius,
+            turn: g.lane_side() as f64,
+            offset: (g.lane_offset as f64 / radius).asin(),
+            gap: (speed * ticks_from_ms(config.gap_ms) as f64) as i32,
+            spacing: VEHICLE_HEIGHT as i32 + min_distance,
+        }
+    }
+
+    // Where a vehicle from this approach joins the circle
+    fn entry_angle(&self, direction: Direction) -> f64 {
+        entry_arm(direction) as f64 * FRAC_PI_2 + self.turn * self.offset
+    }
+
+    // Where a vehicle leaving to travel in this direction comes off the circle
+    fn exit_angle(&self, direction: Direction) -> f64 {
+        exit_arm(direction) as f64 * FRAC_PI_2 - self.turn * self.offset
+    }
+
+    // How far round a vehicle drives from where it joins to its exit
+    fn exit_distance(&self, vehicle: &Vehicle) -> i32 {
+        let exit = exit_arm(vehicle.direction.after(vehicle.route));
+        let turned = self.turn as i32 * (exit - entry_arm(vehicle.direction));
+        let exits = match turned.rem_euclid(4) {
+            0 => 4, // Leaving on the arm it came in on
+            n => n,
+        };
+        (self.radius * (exits as f64 * FRAC_PI_2 - 2.0 * self.offset)) as i32
+    }
+
+    // Position on the circle of a vehicle driving round it
+    fn angle(&self, vehicle: &Vehicle) -> Option<f64> {
+        vehicle.circulated.map(|distance| {
+            self.entry_angle(vehicle.direction) + self.turn * distance as f64 / self.radius
+        })
+    }
+
+    // Rotation of the northbound sprite for a vehicle on the circle, in degrees
+    pub fn heading(&self, vehicle: &Vehicle) -> Option<f64> {
+        let facing = if self.turn > 0.0 { PI } else { 0.0 };
+        self.angle(vehicle)
+            .map(|angle| (angle + facing).to_degrees().rem_euclid(360.0))
+    }
+
+    // Whether a vehicle on its approach has got to the circle
+    pub fn reached(&self, vehicle: &Vehicle) -> bool {
+        let x = (vehicle.position.x + VEHICLE_WIDTH as i32 / 2 - self.g.center_x) as f64;
+        let y = (vehicle.position.y + VEHICLE_HEIGHT as i32 / 2 - self.g.center_y) as f64;
+        let still_to_go = match vehicle.direction {
+            Direction::North => y,
+            Direction::South => -y,
+            Direction::East => -x,
+            Direction::West => x,
+        };
+        still_to_go <= self.radius * self.offset.cos()
+    }
+
+    pub fn join(&self, vehicle: &mut Vehicle) {
+        vehicle.circulated = Some(0);
+        self.place(vehicle);
+    }
+
+    // Drives a vehicle on round the circle, and off it at its exit
+    pub fn drive(&self, vehicle: &mut Vehicle, speed: i32) {
+        let Some(distance) = vehicle.circulated else {
+            return;
+        };
+        if distance + speed < self.exit_distance(vehicle) {
+            vehicle.circulated = Some(distance + speed);
+            self.place(vehicle);
+            return;
+        }
+
+        // Onto the exit lane, lined up with the vehicles already on it
+        let direction = vehicle.direction.after(vehicle.route);
+        let (x, y) = self.point(self.exit_angle(direction));
+        let g = &self.g;
+        let lane_offset = g.lane_offset * g.lane_side();
+        vehicle.position = match direction {
+            Direction::North => Point::new(
+                g.center_x - lane_offset - VEHICLE_WIDTH as i32 / 2,
+                y - VEHICLE_HEIGHT as i32 / 2,
+            ),
+            Direction::South => Point::new(
+                g.center_x + lane_offset - VEHICLE_WIDTH as i32 / 2,
+                y - VEHICLE_HEIGHT as i32 / 2,
+            ),
+            Direction::East => Point::new(
+                x - VEHICLE_WIDTH as i32 / 2,
+                g.center_y - lane_offset - VEHICLE_HEIGHT as i32 / 2,
+            ),
+            Direction::West => Point::new(
+                x - VEHICLE_WIDTH as i32 / 2,
+                g.center_y + lane_offset - VEHICLE_HEIGHT as i32 / 2,
+            ),
+        };
+        vehicle.direction = direction;
+        vehicle.circulated = None;
+        vehicle.has_passed_intersection = true;
+    }
+
+    // Whether a vehicle waiting at its yield line may go: nothing on the
+    // circle just past its entry, and nothing due there within the gap that
+    // isn't leaving before it gets there
+    pub fn entry_clear(&self, vehicle: &Vehicle, vehicles: &[Vehicle]) -> bool {
+        let entry = self.entry_angle(vehicle.direction);
+        !vehicles.iter().any(|other| {
+            let Some(angle) = self.angle(other) else {
+                return false;
+            };
+            let upstream = (self.radius * (self.turn * (entry - angle)).rem_euclid(TAU)) as i32;
+            let downstream = (self.radius * (self.turn * (angle - entry)).rem_euclid(TAU)) as i32;
+            let still_to_go = self.exit_distance(other) - other.circulated.unwrap_or(0);
+            (upstream <= self.gap.max(self.spacing) && upstream < still_to_go)
+                || downstream < self.spacing
+        })
+    }
+
+    // Whether a vehicle on the circle has to wait for the one ahead of it
+    pub fn blocked(&self, vehicle: &Vehicle, vehicles: &[Vehicle]) -> bool {
+        let Some(angle) = self.angle(vehicle) else {
+            return false;
+        };
+        vehicles.iter().any(|other| {
+            other.id != vehicle.id
+                && self.angle(other).is_some_and(|other_angle| {
+                    let ahead = (self.turn * (other_angle - angle)).rem_euclid(TAU);
+                    ((self.radius * ahead) as i32) < self.spacing
+                })
+        })
+    }
+
+    // Screen point on the middle of the circulating lane
+    fn point(&self, angle: f64) -> (i32, i32) {
+        (
+            self.g.center_x + (self.radius * angle.cos()).round() as i32,
+            self.g.center_y + (self.radius * angle.sin()).round() as i32,
+        )
+    }
+
+    // Centres the sprite on the vehicle's point on the circle
+    fn place(&self, vehicle: &mut Vehicle) {
+        if let Some(angle) = self.angle(vehicle) {
+            let (x, y) = self.point(angle);
+            vehicle.position =
+                Point::new(x - VEHICLE_WIDTH as i32 / 2, y - VEHICLE_HEIGHT as i32 / 2);
+        }
+    }
+}
+
+// Arm a vehicle travelling in this direction comes in on, counted clockwise
+// from the east arm
+fn entry_arm(direction: Direction) -> i32 {
+    match direction {
+        Direction::West => 0,
+        Direction::North => 1,
+        Direction::East => 2,
+        Direction::South => 3,
+    }
+}
+
+// Arm a vehicle leaves on to travel in this direction
+fn exit_arm(direction: Direction) -> i32 {
+    (entry_arm(direction) + 2) % 4
+}
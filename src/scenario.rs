@@ -0,0 +1,138 @@
+use crate::config::FlashMode;
+use crate::{Direction, Input, Route, TrafficLightState};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+// A scripted run loaded from TOML: timed spawns, approach closures and forced
+// light states, applied as inputs when the simulation reaches their tick
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub description: String,
+    pub ticks: Option<u64>, // Length of a `run`, unless --ticks is given
+    #[serde(default)]
+    pub random_spawns: bool, // Keep generating random vehicles alongside the script
+    #[serde(default, rename = "event")]
+    pub events: Vec<ScenarioEvent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ScenarioEvent {
+    Spawn {
+        tick: u64,
+        direction: Direction, // Direction of travel, as everywhere else
+        route: Route,
+    },
+    CloseApproach {
+        tick: u64,
+        direction: Direction,
+    },
+    OpenApproach {
+        tick: u64,
+        direction: Direction,
+    },
+    ForceLight {
+        tick: u64,
+        direction: Direction,
+        state: TrafficLightState,
+    },
+    ReleaseLight {
+        tick: u64,
+        direction: Direction,
+    },
+    // The light goes dark until released with release_light
+    LightFailure {
+        tick: u64,
+        direction: Direction,
+    },
+    Flash {
+        tick: u64,
+        mode: FlashMode,
+    },
+    // Back to the configured flash schedule
+    EndFlash {
+        tick: u64,
+    },
+}
+
+impl ScenarioEvent {
+    pub fn tick(&self) -> u64 {
+        match *self {
+            ScenarioEvent::Spawn { tick, .. }
+            | ScenarioEvent::CloseApproach { tick, .. }
+            | ScenarioEvent::OpenApproach { tick, .. }
+            | ScenarioEvent::ForceLight { tick, .. }
+            | ScenarioEvent::ReleaseLight { tick, .. }
+            | ScenarioEvent::LightFailure { tick, .. }
+            | ScenarioEvent::Flash { tick, .. }
+            | ScenarioEvent::EndFlash { tick } => tick,
+        }
+    }
+
+    pub fn input(&self) -> Input {
+        match *self {
+            ScenarioEvent::Spawn {
+                direction, route, ..
+            } => Input::SpawnOnRoute(direction, route),
+            ScenarioEvent::CloseApproach { direction, .. } => Input::CloseApproach(direction),
+            ScenarioEvent::OpenApproach { direction, .. } => Input::OpenApproach(direction),
+            ScenarioEvent::ForceLight {
+                direction, state, ..
+            } => Input::ForceLight(direction, Some(state)),
+            ScenarioEvent::ReleaseLight { direction, .. } => Input::ForceLight(direction, None),
+            ScenarioEvent::LightFailure { direction, .. } => {
+                Input::ForceLight(direction, Some(TrafficLightState::Dark))
+            }
+            ScenarioEvent::Flash { mode, .. } => Input::SetFlash(Some(mode)),
+            ScenarioEvent::EndFlash { .. } => Input::SetFlash(None),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut scenario: Scenario = toml::from_str(&text)
+            .map_err(|e| format!("Invalid scenario {}: {}", path.display(), e))?;
+        // Events on the same tick keep their order from the file
+        scenario.events.sort_by_key(ScenarioEvent::tick);
+        Ok(scenario)
+    }
+}
+
+// Hands out scenario events as inputs once the simulation reaches their tick
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    next_event: usize,
+}
+
+impl ScenarioRunner {
+    pub fn new(scenario: Scenario) -> Self {
+        ScenarioRunner {
+            scenario,
+            next_event: 0,
+        }
+    }
+
+    pub fn scenario(&self) -> &Scenario {
+        &self.scenario
+    }
+
+    // Events whose tick has already passed (e.g. when starting from a later
+    // snapshot) fire straight away
+    pub fn due(&mut self, tick: u64) -> Vec<Input> {
+        let mut inputs = Vec::new();
+        while let Some(event) = self.scenario.events.get(self.next_event) {
+            if event.tick() > tick {
+                break;
+            }
+            inputs.push(event.input());
+            self.next_event += 1;
+        }
+        inputs
+    }
+}
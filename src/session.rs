@@ -0,0 +1,166 @@
+use crate::cli::{OutputArgs, SimArgs};
+use crate::config::{Config, ControlMode, ControllerKind};
+use crate::export::Exporter;
+use crate::replay::Recorder;
+use crate::scenario::{Scenario, ScenarioRunner};
+use crate::{Input, TICKS_PER_SECOND, TrafficSystem};
+
+// A live simulation together with everything that observes it: the exporter,
+// the replay recorder, the event log and an optional scenario script. Shared by
+// the play and run commands.
+pub struct Session {
+    pub system: TrafficSystem,
+    pub seed: u64,
+    exporter: Option<Exporter>,
+    recorder: Option<Recorder>,
+    scenario: Option<ScenarioRunner>,
+    log_events: bool,
+}
+
+impl Session {
+    pub fn start(
+        sim: &SimArgs,
+        output: &OutputArgs,
+        config: Config,
+        start_from: Option<TrafficSystem>,
+    ) -> Result<Self, String> {
+        let seed = sim.seed.unwrap_or_else(rand::random);
+        let exporter = match output.export {
+            Some(format) => Some(Exporter::new(&output.output_dir, format)?),
+            None => None,
+        };
+        let recorder = output
+            .record
+            .clone()
+            .map(|path| Recorder::new(path, seed, config.clone()));
+        let scenario = match &sim.scenario {
+            Some(path) => Some(ScenarioRunner::new(Scenario::load(path)?)),
+            None => None,
+        };
+
+        let mut session = Session {
+            system: TrafficSystem::new(config, seed),
+            seed,
+            exporter,
+            recorder,
+            scenario,
+            log_events: output.log_events,
+        };
+        if let Some(snapshot) = start_from {
+            session.apply(Input::Restore(Box::new(snapshot)));
+        }
+        Ok(session)
+    }
+
+    // Inputs go through the recorder first so replays see exactly what the simulation saw
+    pub fn apply(&mut self, input: Input) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_input(input.clone());
+        }
+        self.system.apply(input);
+    }
+
+    pub fn scenario(&self) -> Option<&Scenario> {
+        self.scenario.as_ref().map(ScenarioRunner::scenario)
+    }
+
+    // Runs get random demand unless the config sets arrival rates or a scenario
+    // supplies the vehicles (and doesn't ask for random spawns as well)
+    pub fn random_spawns(&self) -> bool {
+        match self.scenario() {
+            Some(scenario) => scenario.random_spawns,
+            None => !self.system.config.demand.is_enabled(),
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), String> {
+        // Scenario events go through apply so recordings replay without the scenario file
+        let due = match self.scenario.as_mut() {
+            Some(scenario) => scenario.due(self.system.tick),
+            None => Vec::new(),
+        };
+        for input in due {
+            self.apply(input);
+        }
+
+        self.system.update();
+        if let Some(exporter) = self.exporter.as_mut() {
+            exporter.record_tick(&self.system)?;
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_frame(&self.system);
+        }
+        Ok(())
+    }
+
+    // Passes the events emitted since the last call on to the log and exporter
+    pub fn flush_events(&mut self) -> Result<(), String> {
+        let events = self.system.take_events();
+        if self.log_events {
+            for event in &events {
+                println!(
+                    "[tick {}] vehicle {} {:?}",
+                    event.tick, event.vehicle.0, event.kind
+                );
+            }
+        }
+        if let Some(exporter) = self.exporter.as_mut() {
+            exporter.record_events(&events)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<TrafficSystem, String> {
+        self.flush_events()?;
+        if let Some(exporter) = self.exporter {
+            exporter.finish(&self.system)?;
+        }
+        if let Some(recorder) = self.recorder {
+            recorder.save()?;
+        }
+        Ok(self.system)
+    }
+}
+
+pub fn print_summary(system: &TrafficSystem) {
+    let metrics = &system.metrics;
+    let seconds = |ticks: f64| ticks / TICKS_PER_SECOND as f64;
+    println!(
+        "Simulated {} ticks ({:.1} s)",
+        system.tick,
+        seconds(system.tick as f64)
+    );
+    println!(
+        "Vehicles spawned: {}, exited: {}",
+        metrics.vehicles_spawned, metrics.vehicles_exited
+    );
+    println!(
+        "Mean travel time: {:.2} s, mean wait: {:.2} s, max wait: {:.2} s",
+        seconds(metrics.mean_travel_ticks()),
+        seconds(metrics.mean_wait_ticks()),
+        seconds(metrics.max_wait_ticks as f64)
+    );
+    println!(
+        "Longest wait at the stop line: {:.2} s",
+        seconds(metrics.longest_stop_line_delay_ticks(system.tick) as f64)
+    );
+    println!(
+        "Entry queue: mean delay {:.2} s, {} vehicles delayed, longest {}",
+        seconds(metrics.mean_entry_delay_ticks()),
+        metrics.vehicles_delayed_at_entry,
+        metrics.max_entry_queue
+    );
+    if system.config.intersection.control == ControlMode::Reservation {
+        println!(
+            "Reservations granted: {}",
+            system.reservations.requests_granted
+        );
+    } else {
+        println!("Phase switches: {}", metrics.phase_switches);
+    }
+    if system.config.signals.controller == ControllerKind::Webster
+        && let Some(plan) = system.webster.plan()
+    {
+        println!("Webster plan: {}", plan);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::TrafficSystem;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Bumped whenever TrafficSystem changes shape so stale snapshots are rejected
+const SNAPSHOT_VERSION: u32 = 22;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    state: TrafficSystem,
+}
+
+// Writes the full simulation state (config, vehicles, lights, timers, RNG) as JSON
+pub fn save(system: &TrafficSystem, path: &Path) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        state: system.clone(),
+    };
+    serde_json::to_writer_pretty(&mut out, &snapshot)
+        .map_err(|e| format!("Failed to write snapshot {}: {}", path.display(), e))?;
+    out.flush().map_err(|e| e.to_string())
+}
+
+pub fn load(path: &Path) -> Result<TrafficSystem, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Failed to read snapshot {}: {}", path.display(), e))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(format!(
+            "Snapshot {} has version {}, expected {}",
+            path.display(),
+            snapshot.version,
+            SNAPSHOT_VERSION
+        ));
+    }
+    Ok(snapshot.state)
+}
+
+// serde helpers for sdl2's Point, stored as [x, y]
+pub mod point {
+    use sdl2::rect::Point;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(point: &Point, serializer: S) -> Result<S::Ok, S::Error> {
+        (point.x, point.y).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Point, D::Error> {
+        let (x, y) = <(i32, i32)>::deserialize(deserializer)?;
+        Ok(Point::new(x, y))
+    }
+}
@@ -0,0 +1,255 @@
+use crate::config::{Config, Geometry};
+use crate::{Direction, Route, VEHICLE_HEIGHT, VEHICLE_WIDTH, Vehicle};
+use rand::Rng;
+
+// Fastest a vehicle moves in one tick. Stop lines are detected in a 5 pixel
+// window, which a longer step could jump over.
+pub const MAX_SPEED: f64 = 5.0;
+
+// Free-flow speeds in pixels per tick: the limit on each road in and out of
+// the junction, and the lower one for turning through it
+#[derive(Debug, Clone, Copy)]
+pub struct SpeedLimits {
+    approach: [(Direction, f64); 4], // By direction of travel on the way in
+    exit: [(Direction, f64); 4],     // By direction of travel on the way out
+    turn: Option<f64>,
+    default: f64, // Limit on roads without their own
+}
+
+impl SpeedLimits {
+    pub fn new(config: &Config) -> Self {
+        let default = config.vehicles.speed as f64;
+        let limit = |direction: Direction, leaving: bool| {
+            config
+                .road
+                .speed_limit
+                .iter()
+                .filter(|limit| {
+                    if leaving {
+                        limit.arm.leaving() == direction
+                    } else {
+                        limit.arm.entering() == direction
+                    }
+                })
+                .find_map(|limit| if leaving { limit.exit } else { limit.approach })
+                .unwrap_or(default)
+        };
+        let directions = [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ];
+        SpeedLimits {
+            approach: directions.map(|d| (d, limit(d, false))),
+            exit: directions.map(|d| (d, limit(d, true))),
+            turn: config.road.turn_speed,
+            default,
+        }
+    }
+
+    // Limit where the vehicle is now. Turning vehicles slow down for the
+    // turn while in the box, and on a roundabout everyone is turning.
+    pub fn limit(&self, vehicle: &Vehicle, g: &Geometry) -> f64 {
+        let links = if vehicle.has_passed_intersection {
+            &self.exit
+        } else {
+            &self.approach
+        };
+        let link = links
+            .iter()
+            .find(|(direction, _)| *direction == vehicle.direction)
+            .map_or(MAX_SPEED, |&(_, limit)| limit);
+        let turning = vehicle.circulated.is_some()
+            || (vehicle.route != Route::Straight && in_box(vehicle, g));
+        match self.turn {
+            Some(turn) if turning => link.min(turn),
+            _ => link,
+        }
+    }
+
+    // Speed the vehicle drives at where it is now, when nothing holds it up
+    pub fn free_flow(&self, vehicle: &Vehicle, g: &Geometry) -> f64 {
+        (self.limit(vehicle, g) * vehicle.desire).min(MAX_SPEED)
+    }
+
+    // Speed round the roundabout, for drivers going at the limit
+    pub fn circulating(&self) -> f64 {
+        self.turn.unwrap_or(self.default)
+    }
+}
+
+// A driver's desired speed as a multiple of the limit: normally distributed
+// around it, cut off so nobody crawls or goes half as fast again
+pub fn draw_desire<R: Rng>(spread: f64, rng: &mut R) -> f64 {
+    // Box-Muller; 1 - u keeps the logarithm finite
+    let u: f64 = rng.r#gen();
+    let v: f64 = rng.r#gen();
+    let z = (-2.0 * (1.0 - u).ln()).sqrt() * (std::f64::consts::TAU * v).cos();
+    (1.0 + spread * z).clamp(0.5, 1.5)
+}
+
+// Whether the middle of the vehicle is inside the intersection box
+fn in_box(vehicle: &Vehicle, g: &Geometry) -> bool {
+    let x = vehicle.position.x + VEHICLE_WIDTH as i32 / 2;
+    let y = vehicle.position.y + VEHICLE_HEIGHT as i32 / 2;
+    (x - g.center_x).abs() < g.half_road && (y - g.center_y).abs() < g.half_road
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VehicleId;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use sdl2::rect::Point;
+
+    fn vehicle(direction: Direction, route: Route, position: Point, passed: bool) -> Vehicle {
+        Vehicle {
+            id: VehicleId(0),
+            position,
+            direction,
+            route,
+            has_turned: false,
+            has_passed_intersection: passed,
+            arrived_at_stop_line: false,
+            entered_intersection: false,
+            wait_ticks: 0,
+            circulated: None,
+            desire: 1.0,
+            carry: 0.0,
+        }
+    }
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).expect("test config should parse")
+    }
+
+    #[test]
+    fn each_arm_uses_its_own_limits() {
+        let config = parse(
+            "[vehicles]\nspeed = 2\n\
+             [[road.speed_limit]]\narm = \"south\"\napproach = 3\nexit = 1.5\n",
+        );
+        let g = config.geometry();
+        let limits = SpeedLimits::new(&config);
+        let free_flow = |direction, passed| {
+            let position = Point::new(g.center_x, g.height - 100);
+            limits.free_flow(&vehicle(direction, Route::Straight, position, passed), &g)
+        };
+        // Coming in from the south is travelling north, going out is travelling south
+        assert_eq!(free_flow(Direction::North, false), 3.0);
+        assert_eq!(free_flow(Direction::South, true), 1.5);
+        assert_eq!(free_flow(Direction::North, true), 2.0);
+        assert_eq!(free_flow(Direction::West, false), 2.0);
+    }
+
+    // Wherever a driver at the limit is, it goes no faster than the limit on
+    // the road it's on
+    #[test]
+    fn drivers_at_the_limit_never_exceed_it() {
+        let config = parse(
+            "[[road.speed_limit]]\narm = \"north\"\napproach = 1\nexit = 4\n\
+             [[road.speed_limit]]\narm = \"south\"\napproach = 3.5\nexit = 0.5\n\
+             [[road.speed_limit]]\narm = \"east\"\napproach = 2.5\n\
+             [[road.speed_limit]]\narm = \"west\"\nexit = 1.5\n",
+        );
+        let g = config.geometry();
+        let limits = SpeedLimits::new(&config);
+        let entry = |direction: Direction, passed: bool| {
+            config
+                .road
+                .speed_limit
+                .iter()
+                .filter(|limit| {
+                    if passed {
+                        limit.arm.leaving() == direction
+                    } else {
+                        limit.arm.entering() == direction
+                    }
+                })
+                .find_map(|limit| if passed { limit.exit } else { limit.approach })
+                .unwrap_or(config.vehicles.speed as f64)
+        };
+        let directions = [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ];
+        let routes = [Route::Straight, Route::Left, Route::Right, Route::UTurn];
+        for direction in directions {
+            for route in routes {
+                for passed in [false, true] {
+                    for step in 0..=g.width.max(g.height) / 5 {
+                        let position = match direction {
+                            Direction::North | Direction::South => Point::new(g.center_x, step * 5),
+                            Direction::East | Direction::West => Point::new(step * 5, g.center_y),
+                        };
+                        let v = vehicle(direction, route, position, passed);
+                        let speed = limits.free_flow(&v, &g);
+                        assert!(
+                            speed <= entry(direction, passed),
+                            "{:?} {:?} at {:?} goes {}",
+                            direction,
+                            route,
+                            position,
+                            speed
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn turning_vehicles_slow_to_the_turn_speed_in_the_box() {
+        let config = parse("[vehicles]\nspeed = 3\n[road]\nturn_speed = 1.5\n");
+        let g = config.geometry();
+        let limits = SpeedLimits::new(&config);
+        let in_box = Point::new(
+            g.center_x - VEHICLE_WIDTH as i32 / 2,
+            g.center_y - VEHICLE_HEIGHT as i32 / 2,
+        );
+        let before_box = Point::new(in_box.x, g.height - 100);
+
+        for route in [Route::Left, Route::Right, Route::UTurn] {
+            let turning = vehicle(Direction::North, route, in_box, false);
+            assert_eq!(limits.free_flow(&turning, &g), 1.5, "{:?}", route);
+            let approaching = vehicle(Direction::North, route, before_box, false);
+            assert_eq!(limits.free_flow(&approaching, &g), 3.0, "{:?}", route);
+        }
+        let straight = vehicle(Direction::North, Route::Straight, in_box, false);
+        assert_eq!(limits.free_flow(&straight, &g), 3.0);
+        let mut circulating = vehicle(Direction::North, Route::Straight, before_box, false);
+        circulating.circulated = Some(0);
+        assert_eq!(limits.free_flow(&circulating, &g), 1.5);
+
+        // A turn speed above the road's limit doesn't speed anyone up
+        let config = parse("[vehicles]\nspeed = 1\n[road]\nturn_speed = 4\n");
+        let limits = SpeedLimits::new(&config);
+        let turning = vehicle(Direction::North, Route::Left, in_box, false);
+        assert_eq!(limits.free_flow(&turning, &g), 1.0);
+    }
+
+    #[test]
+    fn desire_stays_between_half_and_one_and_a_half() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let draws: Vec<f64> = (0..10_000).map(|_| draw_desire(5.0, &mut rng)).collect();
+        assert!(draws.iter().all(|desire| (0.5..=1.5).contains(desire)));
+        // A wide spread reaches both ends of the cut-off
+        assert!(draws.contains(&0.5) && draws.contains(&1.5));
+
+        // A fast driver on a fast road is still held to MAX_SPEED
+        let config = parse("[vehicles]\nspeed = 4\n");
+        let g = config.geometry();
+        let mut fast = vehicle(
+            Direction::North,
+            Route::Straight,
+            Point::new(g.center_x, g.height - 100),
+            false,
+        );
+        fast.desire = 1.5;
+        assert_eq!(SpeedLimits::new(&config).free_flow(&fast, &g), MAX_SPEED);
+    }
+}
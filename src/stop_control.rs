@@ -0,0 +1,249 @@
+use crate::config::{Geometry, IntersectionConfig, ticks_from_ms};
+use crate::speed::SpeedLimits;
+use crate::{Direction, Vehicle, VehicleId};
+use serde::{Deserialize, Serialize};
+
+// What an approach's sign or light tells arriving vehicles to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RightOfWay {
+    Go,      // Green or flashing yellow, or the major road at yield signs
+    Blocked, // Red light
+    Yield,   // Give way to approaches that have Go
+    Stop,    // Stop sign, flashing red or a dark signal: full stop, then give way
+}
+
+// Right of way where vehicles have to give way rather than follow a light.
+// They are queued in the order they reach the stop line, and the front one is
+// let into the intersection once it is its turn: after a full stop where it
+// must stop, and when approaches with Go leave it a big enough gap. One
+// vehicle crosses at a time.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StopControl {
+    arrivals: Vec<(VehicleId, u64)>, // Vehicles waiting at the stop line and when they got there
+    crossing: Option<VehicleId>,     // Let in, until it reaches the middle of the intersection
+}
+
+impl StopControl {
+    pub fn update(
+        &mut self,
+        vehicles: &[Vehicle],
+        g: &Geometry,
+        rights: &[(Direction, RightOfWay)],
+        config: &IntersectionConfig,
+        limits: &SpeedLimits,
+        tick: u64,
+    ) {
+        let right_of_way = |direction: Direction| {
+            rights
+                .iter()
+                .find(|(d, _)| *d == direction)
+                .map_or(RightOfWay::Blocked, |&(_, right)| right)
+        };
+        // Vehicles waiting or crossing on an approach that turned to a light
+        // (e.g. when flashing ends) follow the light instead
+        let gives_way = |id: VehicleId| {
+            vehicles.iter().any(|v| {
+                v.id == id
+                    && !v.has_passed_intersection
+                    && matches!(
+                        right_of_way(v.direction),
+                        RightOfWay::Yield | RightOfWay::Stop
+                    )
+            })
+        };
+        if self.crossing.is_some_and(|id| !gives_way(id)) {
+            self.crossing = None;
+        }
+        self.arrivals.retain(|&(id, _)| gives_way(id));
+
+        for vehicle in vehicles {
+            if vehicle.arrived_at_stop_line
+                && gives_way(vehicle.id)
+                && self.crossing != Some(vehicle.id)
+                && !self.arrivals.iter().any(|&(id, _)| id == vehicle.id)
+            {
+                self.arrivals.push((vehicle.id, tick));
+            }
+        }
+
+        if self.crossing.is_some() {
+            return;
+        }
+        let Some(&(front, arrival)) = self.arrivals.first() else {
+            return;
+        };
+        let Some(front_vehicle) = vehicles.iter().find(|v| v.id == front) else {
+            return;
+        };
+        let stopped_long_enough = right_of_way(front_vehicle.direction) != RightOfWay::Stop
+            || tick - arrival >= ticks_from_ms(config.stop_ms);
+        // Vehicles with Go that would reach the intersection within the gap,
+        // each at its own speed
+        let gap = ticks_from_ms(config.gap_ms) as f64;
+        let gap_accepted = !vehicles.iter().any(|v| {
+            right_of_way(v.direction) == RightOfWay::Go
+                && !v.has_passed_intersection
+                && v.distance_to_stop_line(g) as f64 <= limits.free_flow(v, g) * gap
+        });
+        if stopped_long_enough && gap_accepted {
+            self.arrivals.remove(0);
+            self.crossing = Some(front);
+        }
+    }
+
+    // Whether a vehicle may drive past its stop line
+    pub fn may_enter(&self, right: RightOfWay, vehicle: VehicleId) -> bool {
+        match right {
+            RightOfWay::Go => true,
+            RightOfWay::Blocked => false,
+            RightOfWay::Yield | RightOfWay::Stop => self.crossing == Some(vehicle),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Route;
+    use crate::config::Config;
+    use sdl2::rect::Point;
+
+    fn vehicle(id: u32, direction: Direction, position: Point) -> Vehicle {
+        Vehicle {
+            id: VehicleId(id),
+            position,
+            direction,
+            route: Route::Straight,
+            has_turned: false,
+            has_passed_intersection: false,
+            arrived_at_stop_line: false,
+            entered_intersection: false,
+            wait_ticks: 0,
+            circulated: None,
+            desire: 1.0,
+            carry: 0.0,
+        }
+    }
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).expect("test config should parse")
+    }
+
+    #[test]
+    fn all_way_stop_lets_vehicles_in_by_arrival() {
+        let config = parse("[intersection]\ncontrol = \"all_way_stop\"\nstop_ms = 1000\n");
+        let g = config.geometry();
+        let limits = SpeedLimits::new(&config);
+        let rights = [
+            Direction::North,
+            Direction::South,
+            Direction::East,
+            Direction::West,
+        ]
+        .map(|direction| (direction, RightOfWay::Stop));
+        let stop_ticks = ticks_from_ms(1000);
+        // Listed north first, but east gets to its line first
+        let mut vehicles = [
+            vehicle(
+                1,
+                Direction::North,
+                Point::new(g.center_x - 30, g.center_y + 30),
+            ),
+            vehicle(
+                2,
+                Direction::East,
+                Point::new(g.center_x - 60, g.center_y - 30),
+            ),
+        ];
+        let mut control = StopControl::default();
+        let may_enter =
+            |control: &StopControl, id| control.may_enter(RightOfWay::Stop, VehicleId(id));
+
+        for tick in 0..3 * stop_ticks {
+            if tick == 0 {
+                vehicles[1].arrived_at_stop_line = true;
+            }
+            if tick == 10 {
+                vehicles[0].arrived_at_stop_line = true;
+            }
+            control.update(&vehicles, &g, &rights, &config.intersection, &limits, tick);
+            // East goes after its full stop, north waits while it crosses
+            assert_eq!(may_enter(&control, 2), tick >= stop_ticks, "tick {}", tick);
+            assert!(!may_enter(&control, 1), "tick {}", tick);
+        }
+
+        // Once east is past the middle it's north's turn, having long since
+        // made its stop
+        vehicles[1].has_passed_intersection = true;
+        control.update(
+            &vehicles,
+            &g,
+            &rights,
+            &config.intersection,
+            &limits,
+            3 * stop_ticks,
+        );
+        assert!(may_enter(&control, 1));
+        assert!(!may_enter(&control, 2));
+    }
+
+    #[test]
+    fn minor_road_waits_for_a_long_enough_gap() {
+        let config = parse(
+            "[vehicles]\nspeed = 2\n\
+             [intersection]\ncontrol = \"yield\"\nmajor_road = \"north_south\"\ngap_ms = 2000\n",
+        );
+        let g = config.geometry();
+        let limits = SpeedLimits::new(&config);
+        let rights = [
+            (Direction::North, RightOfWay::Go),
+            (Direction::South, RightOfWay::Go),
+            (Direction::East, RightOfWay::Yield),
+            (Direction::West, RightOfWay::Yield),
+        ];
+        // A northbound vehicle needs 120 ticks for 240 pixels
+        let gap = 2 * ticks_from_ms(2000) as i32;
+        let north_at = |distance: i32| {
+            let mut major = vehicle(1, Direction::North, Point::new(g.center_x - 30, 0));
+            major.position.y = g.center_y + g.stop_line - 5 + distance;
+            assert_eq!(major.distance_to_stop_line(&g), distance);
+            major
+        };
+        let mut minor = vehicle(
+            2,
+            Direction::East,
+            Point::new(g.center_x - 60, g.center_y - 30),
+        );
+        minor.arrived_at_stop_line = true;
+        let mut control = StopControl::default();
+
+        // The major-road vehicle closes in, then drives through
+        for (tick, distance) in [(0, 100), (1, gap), (2, 40), (3, 0)] {
+            let vehicles = [north_at(distance), minor.clone()];
+            control.update(&vehicles, &g, &rights, &config.intersection, &limits, tick);
+            assert!(
+                !control.may_enter(RightOfWay::Yield, VehicleId(2)),
+                "gap of {} pixels accepted",
+                distance
+            );
+        }
+        let mut past = north_at(-100);
+        past.has_passed_intersection = true;
+        control.update(
+            &[past, minor.clone()],
+            &g,
+            &rights,
+            &config.intersection,
+            &limits,
+            4,
+        );
+        // No full stop needed at a yield sign
+        assert!(control.may_enter(RightOfWay::Yield, VehicleId(2)));
+
+        // A gap just longer than gap_ms is taken straight away
+        let mut control = StopControl::default();
+        let vehicles = [north_at(gap + 1), minor.clone()];
+        control.update(&vehicles, &g, &rights, &config.intersection, &limits, 0);
+        assert!(control.may_enter(RightOfWay::Yield, VehicleId(2)));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::config::{WebsterConfig, ticks_from_ms};
+use crate::detectors::Detectors;
+use crate::{Direction, TICKS_PER_SECOND};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Phases run in this order every cycle, one approach each
+const PHASES: [Direction; 4] = [
+    Direction::North,
+    Direction::East,
+    Direction::South,
+    Direction::West,
+];
+
+// A fixed-time plan: each phase's green followed by the lost time as all-red
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebsterPlan {
+    pub cycle_ticks: u64,
+    pub greens: Vec<(Direction, u64)>, // Green ticks per phase, in running order
+    lost_ticks: u64,
+}
+
+impl WebsterPlan {
+    // Webster's optimal cycle C0 = (1.5 L + 5) / (1 - Y), where L is the total
+    // lost time and Y the sum of the flow ratios y = q / s, with the effective
+    // green C0 - L split in proportion to y. Flows are in PHASES order.
+    pub fn compute(config: &WebsterConfig, flows: [f64; 4]) -> Self {
+        let lost_s = config.lost_time_ms as f64 / 1000.0 * PHASES.len() as f64;
+        let ratios: Vec<f64> = PHASES
+            .iter()
+            .zip(flows)
+            .map(|(&direction, flow)| flow / config.approach(direction).saturation_flow)
+            .collect();
+        let y: f64 = ratios.iter().sum();
+
+        let min_cycle_s = config.min_cycle_ms as f64 / 1000.0;
+        let max_cycle_s = config.max_cycle_ms as f64 / 1000.0;
+        // Oversaturated (Y >= 1) has no optimum, so run the longest cycle allowed
+        let cycle_s = if y < 1.0 {
+            ((1.5 * lost_s + 5.0) / (1.0 - y)).clamp(min_cycle_s, max_cycle_s)
+        } else {
+            max_cycle_s
+        };
+        let effective_green_s = (cycle_s - lost_s).max(0.0);
+
+        let min_green = ticks_from_ms(config.min_green_ms);
+        let lost_ticks = ticks_from_ms(config.lost_time_ms);
+        let greens: Vec<(Direction, u64)> = PHASES
+            .iter()
+            .zip(&ratios)
+            .map(|(&direction, &ratio)| {
+                let share = if y > 0.0 {
+                    ratio / y
+                } else {
+                    1.0 / PHASES.len() as f64
+                };
+                let green = (share * effective_green_s * TICKS_PER_SECOND as f64).round() as u64;
+                (direction, green.max(min_green))
+            })
+            .collect();
+        let cycle_ticks = greens.iter().map(|&(_, green)| green + lost_ticks).sum();
+
+        WebsterPlan {
+            cycle_ticks,
+            greens,
+            lost_ticks,
+        }
+    }
+
+    // Green approach at a point in the cycle, or None during lost time
+    fn phase_at(&self, mut offset: u64) -> Option<Direction> {
+        for &(direction, green) in &self.greens {
+            if offset < green {
+                return Some(direction);
+            }
+            offset -= green;
+            if offset < self.lost_ticks {
+                return None;
+            }
+            offset -= self.lost_ticks;
+        }
+        None
+    }
+}
+
+impl fmt::Display for WebsterPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = |ticks: u64| ticks as f64 / TICKS_PER_SECOND as f64;
+        write!(f, "cycle {:.1} s, green", seconds(self.cycle_ticks))?;
+        for &(direction, green) in &self.greens {
+            write!(f, " {:?} {:.1} s", direction, seconds(green))?;
+        }
+        write!(f, ", {:.1} s all-red after each", seconds(self.lost_ticks))
+    }
+}
+
+// Runs the Webster plan, starting from the configured flows and, if asked,
+// re-optimising at cycle boundaries from the passage detector counts
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct WebsterController {
+    plan: Option<WebsterPlan>,
+    cycle_start: u64,
+    last_optimised: u64,
+    counts_at_last_optimisation: [u64; 4], // In PHASES order
+}
+
+impl WebsterController {
+    pub fn plan(&self) -> Option<&WebsterPlan> {
+        self.plan.as_ref()
+    }
+
+    // Returns the approach that should have the green this tick
+    pub fn update(
+        &mut self,
+        detectors: &Detectors,
+        config: &WebsterConfig,
+        tick: u64,
+    ) -> Option<Direction> {
+        let plan = match self.plan.take() {
+            Some(plan) if tick - self.cycle_start >= plan.cycle_ticks => {
+                self.cycle_start = tick;
+                let reoptimise = ticks_from_ms(config.reoptimise_ms);
+                if reoptimise > 0 && tick - self.last_optimised >= reoptimise {
+                    self.optimise_from_counts(detectors, config, tick)
+                } else {
+                    plan
+                }
+            }
+            Some(plan) => plan,
+            None => {
+                self.cycle_start = tick;
+                self.last_optimised = tick;
+                self.counts_at_last_optimisation = PHASES.map(|d| detectors.reading(d).count);
+                WebsterPlan::compute(config, PHASES.map(|d| config.approach(d).flow))
+            }
+        };
+        let green = plan.phase_at(tick - self.cycle_start);
+        self.plan = Some(plan);
+        green
+    }
+
+    // Measured flow is the vehicles counted since the last optimisation per hour
+    fn optimise_from_counts(
+        &mut self,
+        detectors: &Detectors,
+        config: &WebsterConfig,
+        tick: u64,
+    ) -> WebsterPlan {
+        let counts = PHASES.map(|d| detectors.reading(d).count);
+        let hours = (tick - self.last_optimised) as f64 / TICKS_PER_SECOND as f64 / 3600.0;
+        let mut flows = [0.0; 4];
+        for i in 0..PHASES.len() {
+            flows[i] = (counts[i] - self.counts_at_last_optimisation[i]) as f64 / hours;
+        }
+        self.last_optimised = tick;
+        self.counts_at_last_optimisation = counts;
+        WebsterPlan::compute(config, flows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flows with the given flow ratios y, in PHASES order, at the default
+    // saturation flow of 1800 vehicles per hour
+    fn flows(y: [f64; 4]) -> [f64; 4] {
+        y.map(|y| y * 1800.0)
+    }
+
+    fn green(plan: &WebsterPlan, direction: Direction) -> u64 {
+        plan.greens
+            .iter()
+            .find(|&&(d, _)| d == direction)
+            .map(|&(_, green)| green)
+            .unwrap()
+    }
+
+    #[test]
+    fn optimal_cycle_with_greens_in_proportion_to_flow_ratios() {
+        // L = 4 x 2 s = 8 s, Y = 0.7: C0 = (1.5 x 8 + 5) / 0.3 = 56.7 s,
+        // leaving 48.7 s of green to split 0.25 : 0.2 : 0.15 : 0.1
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), flows([0.25, 0.2, 0.15, 0.1]));
+        assert_eq!(plan.cycle_ticks, 3400);
+        assert_eq!(green(&plan, Direction::North), 1043);
+        assert_eq!(green(&plan, Direction::East), 834);
+        assert_eq!(green(&plan, Direction::South), 626);
+        assert_eq!(green(&plan, Direction::West), 417);
+        assert_eq!(plan.lost_ticks, 120);
+    }
+
+    #[test]
+    fn short_cycle_is_raised_to_the_minimum() {
+        // Y = 0.1 gives C0 = 18.9 s, below the 30 s minimum
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), flows([0.025; 4]));
+        assert_eq!(plan.cycle_ticks, 30 * TICKS_PER_SECOND as u64);
+        assert!(plan.greens.iter().all(|&(_, green)| green == 330));
+    }
+
+    #[test]
+    fn long_cycle_is_cut_to_the_maximum() {
+        // Y = 0.9 gives C0 = 170 s, above the 120 s maximum
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), flows([0.225; 4]));
+        assert_eq!(plan.cycle_ticks, 120 * TICKS_PER_SECOND as u64);
+    }
+
+    #[test]
+    fn oversaturated_junction_runs_the_longest_cycle() {
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), flows([0.5, 0.3, 0.3, 0.1]));
+        assert_eq!(plan.cycle_ticks, 120 * TICKS_PER_SECOND as u64);
+        // Still split in proportion to y: 112 s of green over Y = 1.2
+        assert_eq!(green(&plan, Direction::North), 2800);
+        assert_eq!(green(&plan, Direction::West), 560);
+    }
+
+    #[test]
+    fn no_flow_splits_the_minimum_cycle_evenly() {
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), [0.0; 4]);
+        assert_eq!(plan.cycle_ticks, 30 * TICKS_PER_SECOND as u64);
+        assert!(plan.greens.iter().all(|&(_, green)| green == 330));
+    }
+
+    #[test]
+    fn light_approach_gets_at_least_min_green() {
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), flows([0.4, 0.001, 0.2, 0.1]));
+        assert_eq!(green(&plan, Direction::East), 240);
+    }
+
+    #[test]
+    fn phases_follow_each_other_with_all_red_between() {
+        let plan = WebsterPlan::compute(&WebsterConfig::default(), [0.0; 4]);
+        assert_eq!(plan.phase_at(0), Some(Direction::North));
+        assert_eq!(plan.phase_at(329), Some(Direction::North));
+        assert_eq!(plan.phase_at(330), None);
+        assert_eq!(plan.phase_at(449), None);
+        assert_eq!(plan.phase_at(450), Some(Direction::East));
+    }
+}